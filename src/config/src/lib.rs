@@ -46,6 +46,9 @@ pub const MAX_FD_NUM: usize = 4096;
 /// 最大的输入事件数量
 pub const MAX_INPUT_EVENT_NUM: usize = 1024;
 
+/// 串口输入缓冲区大小
+pub const UART_INPUT_BUF_SIZE: usize = 4096;
+
 /// 如果 elf 的 phdr 指示 base 是 0(如 libc-test 的 libc.so)，则需要找一个非0的位置放置
 /// 我们将其从 0x4000_0000 开始放置。主要用于动态链接库使用
 pub const ELF_BASE_RELOCATE: usize = 0x400_0000;
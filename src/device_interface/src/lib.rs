@@ -1,31 +1,311 @@
 #![no_std]
 
+extern crate alloc;
+
+use alloc::sync::Arc;
 use constants::io::RtcTime;
-use constants::AlienResult;
+use constants::{AlienResult, LinuxErrno};
 use core::any::Any;
+use core::cmp::min;
+use core::task::{Context, Poll};
 
 //设备基础接口
-pub trait DeviceBase: Sync + Send {
+pub trait DeviceBase: Sync + Send + AsAny {
     fn hand_irq(&self);
 }
 
+//取得具体类型的Any, 对所有'static类型自动实现, 设备无需自己实现
+pub trait AsAny {
+    fn as_any(&self) -> &dyn Any;
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync>;
+}
+
+impl<T: Any + Send + Sync> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn Any + Send + Sync> {
+        self
+    }
+}
+
+impl dyn DeviceBase {
+    //具体类型为T时返回其引用.
+    //注意对Arc<dyn DeviceBase>直接调用as_any得到的是Arc自身, 需要使用这里的方法
+    pub fn downcast_ref<T: DeviceBase + Any>(&self) -> Option<&T> {
+        self.as_any().downcast_ref()
+    }
+
+    //具体类型为T时转换为Arc<T>, 否则返回None
+    pub fn downcast_arc<T: DeviceBase + Any>(self: Arc<Self>) -> Option<Arc<T>> {
+        self.into_any().downcast().ok()
+    }
+}
+
+//块设备几何信息
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockDeviceInfo {
+    pub logical_sector_size: usize,  //逻辑扇区大小
+    pub physical_sector_size: usize, //物理扇区大小
+    pub total_sectors: usize,        //逻辑扇区总数
+    pub supports_trim: bool,         //是否支持TRIM/discard
+    pub supports_flush: bool,        //是否支持flush
+}
+
+//块设备健康状态, 按严重程度递增
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HealthStatus {
+    #[default]
+    Healthy, //没有出现过错误
+    Degraded, //出现过错误或重试, 仍可正常使用
+    Failing,  //持续出错, 已停止访问或即将失效
+}
+
+//块设备健康信息, 计数从设备初始化开始累计
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlockHealth {
+    pub read_errors: usize,  //重试后仍失败的读取次数
+    pub write_errors: usize, //重试后仍失败的写入次数
+    pub retries: usize,      //重试次数
+    pub status: HealthStatus,
+}
+
+impl BlockHealth {
+    //合并两层或多个成员的健康信息: 计数相加, 状态取较差的一个
+    pub fn merge(self, other: BlockHealth) -> BlockHealth {
+        BlockHealth {
+            read_errors: self.read_errors + other.read_errors,
+            write_errors: self.write_errors + other.write_errors,
+            retries: self.retries + other.retries,
+            status: self.status.max(other.status),
+        }
+    }
+}
+
+//块设备访问模式提示
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Advice {
+    Normal,     //取消之前的提示
+    WillNeed,   //即将访问, 预读进缓存
+    DontNeed,   //不再访问, 写回后移出缓存
+    Sequential, //顺序访问, 对该区域开启预读
+}
+
+//command的请求格式, 小端序: type(4) 保留(4) sector(8), 之后是该命令的参数.
+//与virtio-blk的请求头相同, sector总是以512字节为单位, 不用的字段填0
+pub const BLK_CMD_HEADER_SIZE: usize = 16;
+pub const BLK_CMD_FLUSH: u32 = 4; //刷新写缓存, 没有参数, 响应长度为0
+pub const BLK_CMD_GET_ID: u32 = 8; //读取设备标识, 响应为最多20字节的标识字符串, 返回其长度
+pub const BLK_CMD_DISCARD: u32 = 11; //丢弃从sector开始的扇区, 参数为扇区数(4) 保留(4), 之后内容不确定
+
 pub trait BlockDevice: Send + Sync + DeviceBase {
     fn read(&self, buf: &mut [u8], offset: usize) -> AlienResult<usize>;
     fn write(&self, buf: &[u8], offset: usize) -> AlienResult<usize>;
-    fn size(&self) -> usize;
+    //设备大小(字节)
+    fn size(&self) -> usize {
+        self.sector_count() * self.sector_size()
+    }
+    //扇区数
+    fn sector_count(&self) -> usize;
+    //扇区大小, 默认512字节
+    fn sector_size(&self) -> usize {
+        512
+    }
+    //设备偏好的单次传输大小(字节)
+    fn optimal_io_size(&self) -> usize {
+        self.sector_size()
+    }
     fn flush(&self) -> AlienResult<()>;
+    //flush是否有实际作用, 为false时文件系统可以跳过fsync
+    fn needs_flush(&self) -> bool {
+        true
+    }
+    //写入并保证数据到达持久存储后才返回, 用于日志文件系统的顺序保证
+    fn write_barrier(&self, buf: &[u8], offset: usize) -> AlienResult<usize> {
+        let len = self.write(buf, offset)?;
+        self.flush()?;
+        Ok(len)
+    }
+    //将[offset, offset+len)清零
+    fn write_zeroes(&self, offset: usize, len: usize) -> AlienResult<()> {
+        let zeros = [0u8; 4096];
+        let mut count = 0;
+        while count < len {
+            let n = min(zeros.len(), len - count);
+            self.write(&zeros[..n], offset + count)?;
+            count += n;
+        }
+        Ok(())
+    }
+    //访问模式提示, 默认忽略
+    fn advise(&self, _offset: usize, _len: usize, _hint: Advice) {}
+    //健康状态, 默认没有统计, 总是报告正常
+    fn health(&self) -> BlockHealth {
+        BlockHealth::default()
+    }
+    //设备专用命令: req是BLK_CMD_*请求, 响应写入resp, 返回响应的长度.
+    //不支持命令通道时返回ENOSYS, 不支持该命令时返回EOPNOTSUPP
+    fn command(&self, _req: &[u8], _resp: &mut [u8]) -> AlienResult<usize> {
+        Err(LinuxErrno::ENOSYS)
+    }
+    fn info(&self) -> BlockDeviceInfo {
+        BlockDeviceInfo {
+            logical_sector_size: self.sector_size(),
+            physical_sector_size: self.sector_size(),
+            total_sectors: self.sector_count(),
+            supports_trim: false,
+            supports_flush: true,
+        }
+    }
+}
+
+impl dyn BlockDevice {
+    //按512字节扇区顺序读取整个设备
+    pub fn sectors(&self) -> SectorIter<'_> {
+        SectorIter::new(self)
+    }
+}
+
+//逐个读取512字节扇区的迭代器, 产生(扇区号, 扇区数据), 读取经过设备自身的缓存.
+//扇区号以512字节为单位, 到size()/512为止. 读取出错时产生一次错误后结束
+pub struct SectorIter<'a> {
+    device: &'a dyn BlockDevice,
+    next: usize, //下一个扇区号
+    end: usize,  //扇区总数
+}
+
+impl<'a> SectorIter<'a> {
+    pub fn new(device: &'a dyn BlockDevice) -> Self {
+        Self {
+            device,
+            next: 0,
+            end: device.size() / 512,
+        }
+    }
+}
+
+impl Iterator for SectorIter<'_> {
+    type Item = AlienResult<(usize, [u8; 512])>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.end {
+            return None;
+        }
+        let sector = self.next;
+        let mut buf = [0u8; 512];
+        let res = match self.device.read(&mut buf, sector * 512) {
+            Ok(512) => Ok((sector, buf)),
+            Ok(_) => Err(LinuxErrno::EIO),
+            Err(e) => Err(e),
+        };
+        //出错后不再继续读取
+        self.next = if res.is_ok() { sector + 1 } else { self.end };
+        Some(res)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.end - self.next;
+        (0, Some(remaining))
+    }
+}
+
+//异步块设备接口, 请求未完成时返回Pending, 完成后通过cx中的waker唤醒
+pub trait AsyncBlockDevice: BlockDevice {
+    fn poll_read(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        cx: &mut Context<'_>,
+    ) -> Poll<AlienResult<usize>>;
 }
 
 //底层块设备接口
 pub trait LowBlockDevice {
     fn read_block(&mut self, block_id: usize, buf: &mut [u8]) -> AlienResult<()>;
     fn write_block(&mut self, block_id: usize, buf: &[u8]) -> AlienResult<()>;
-    fn capacity(&self) -> usize;
-    fn flush(&mut self) {}
+    //从block_id开始连续读取多个块, buf长度必须是块大小的整数倍, 否则返回EINVAL.
+    //默认逐块调用read_block, 支持多块请求的设备可以一次完成
+    fn read_blocks(&mut self, block_id: usize, buf: &mut [u8]) -> AlienResult<()> {
+        let block_size = self.block_size();
+        if !buf.len().is_multiple_of(block_size) {
+            return Err(LinuxErrno::EINVAL);
+        }
+        for (i, block) in buf.chunks_mut(block_size).enumerate() {
+            self.read_block(block_id + i, block)?;
+        }
+        Ok(())
+    }
+    //从block_id开始连续写入多个块, 要求同read_blocks
+    fn write_blocks(&mut self, block_id: usize, buf: &[u8]) -> AlienResult<()> {
+        let block_size = self.block_size();
+        if !buf.len().is_multiple_of(block_size) {
+            return Err(LinuxErrno::EINVAL);
+        }
+        for (i, block) in buf.chunks(block_size).enumerate() {
+            self.write_block(block_id + i, block)?;
+        }
+        Ok(())
+    }
+    //容量(块数), 无法获取时返回错误
+    fn capacity(&self) -> AlienResult<usize>;
+    //块大小(字节), read_block/write_block的buf长度, 默认512, 最大4096
+    fn block_size(&self) -> usize {
+        512
+    }
+    //设备偏好的单次传输大小(字节), 默认为一个块
+    fn optimal_io_size(&self) -> usize {
+        512
+    }
+    fn flush(&mut self) -> AlienResult<()> {
+        Ok(())
+    }
+    //flush是否有实际作用
+    fn needs_flush(&self) -> bool {
+        true
+    }
+    //设备是否只读
+    fn read_only(&self) -> bool {
+        false
+    }
+    //应答设备中断, 默认无操作
+    fn ack_interrupt(&mut self) {}
+    //上次调用以来容量是否发生变化, 例如在线扩容, 默认容量不会变化
+    fn capacity_changed(&mut self) -> bool {
+        false
+    }
+    //设备自身报告的健康信息(如SMART数据), 默认没有
+    fn health(&self) -> BlockHealth {
+        BlockHealth::default()
+    }
+    //设备专用命令, 同BlockDevice::command
+    fn command(&mut self, _req: &[u8], _resp: &mut [u8]) -> AlienResult<usize> {
+        Err(LinuxErrno::ENOSYS)
+    }
+    //将从block_id开始的count个块清零
+    fn write_zeroes(&mut self, block_id: usize, count: usize) -> AlienResult<()> {
+        let zeros = [0u8; 4096];
+        let block_size = self.block_size();
+        for i in block_id..block_id + count {
+            self.write_block(i, &zeros[..block_size])?;
+        }
+        Ok(())
+    }
+    fn info(&self) -> BlockDeviceInfo {
+        BlockDeviceInfo {
+            logical_sector_size: self.block_size(),
+            physical_sector_size: self.block_size(),
+            total_sectors: self.capacity().unwrap_or(0),
+            supports_trim: false,
+            supports_flush: true,
+        }
+    }
 }
 
 pub trait GpuDevice: Send + Sync + Any + DeviceBase {
     fn update_cursor(&self);
+    //帧缓冲区是设备内存, 不归&self所有, 调用者自行保证不同时持有多个可变引用
+    #[allow(clippy::mut_from_ref)]
     fn get_framebuffer(&self) -> &mut [u8];
     fn flush(&self);
     fn resolution(&self) -> (u32, u32);
@@ -47,6 +327,27 @@ pub trait UartDevice: Send + Sync + DeviceBase {
     fn put_bytes(&self, bytes: &[u8]);
     fn have_data_to_get(&self) -> bool;
     fn have_space_to_put(&self) -> bool;
+    //发送缓冲区当前不需等待就能接收的字节数, 默认有空间时为1
+    fn space_to_put(&self) -> usize {
+        self.have_space_to_put() as usize
+    }
+    //发送缓冲区和移位寄存器都已空, 即之前写入的数据已全部发出, 默认认为没有缓冲
+    fn tx_empty(&self) -> bool {
+        true
+    }
+    //设置波特率, 返回硬件实际使用的波特率(最接近的可用值), 不支持时返回None
+    fn set_baud(&self, _baud: u32) -> Option<u32> {
+        None
+    }
 }
 
-pub trait NetDevice: DeviceBase {}
+//网络设备接口, 以完整的以太网帧为单位收发
+pub trait NetDevice: Send + Sync + DeviceBase {
+    //发送一帧, 返回发送的字节数
+    fn send(&self, buf: &[u8]) -> AlienResult<usize>;
+    //接收一帧, buf不足时截断, 返回拷贝的字节数
+    fn receive(&self, buf: &mut [u8]) -> AlienResult<usize>;
+    fn mac_address(&self) -> [u8; 6];
+    fn can_send(&self) -> bool;
+    fn can_receive(&self) -> bool;
+}
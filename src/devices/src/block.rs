@@ -1,22 +1,79 @@
+use alloc::string::String;
 use alloc::sync::Arc;
-use constants::DeviceId;
-use device_interface::BlockDevice;
-use spin::Once;
+use alloc::vec::Vec;
+use constants::{AlienResult, DeviceId, LinuxErrno};
+use core::cmp::min;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use device_interface::{Advice, BlockDevice};
+use ksync::Mutex;
 use vfscore::error::VfsError;
 use vfscore::file::VfsFile;
 use vfscore::inode::{InodeAttr, VfsInode};
 use vfscore::utils::{VfsFileStat, VfsNodeType, VfsPollEvents};
 use vfscore::VfsResult;
 
+use crate::{DeviceClass, DEVICE_IDS, DEVICE_MANAGER};
 use drivers::block_device::GenericBlockDevice;
-pub static BLOCK_DEVICE: Once<Arc<GenericBlockDevice>> = Once::new(); //Once是一个只能被初始化一次的容器
+pub static BLOCK_DEVICE: Mutex<Vec<Arc<GenericBlockDevice>>> = Mutex::new(Vec::new()); //按探测顺序保存所有块设备
 
-//初始化块设备
-pub fn init_block_device(block_device: Arc<GenericBlockDevice>) {
-    // BLOCK_DEVICE.lock().push(block_device);
-    BLOCK_DEVICE.call_once(|| block_device);
+//初始化块设备, 追加到设备列表并返回其序号
+pub fn init_block_device(block_device: Arc<GenericBlockDevice>) -> usize {
+    let mut devices = BLOCK_DEVICE.lock();
+    let idx = devices.len();
+    //块设备只在这里分配设备号, 因此第idx个设备的设备号就是block_device_id(idx)
+    let id = DEVICE_IDS.alloc(DeviceClass::VirtioBlock);
+    DEVICE_MANAGER.register(id, block_device.clone());
+    devices.push(block_device);
+    idx
 }
 
+//按序号获取块设备
+pub fn get_block_device(idx: usize) -> Option<Arc<GenericBlockDevice>> {
+    BLOCK_DEVICE.lock().get(idx).cloned()
+}
+
+//按探测顺序刷新所有块设备, 用于关机/重启前. 某个设备失败不影响其余设备,
+//返回所有失败设备的设备号和错误
+pub fn sync_all_block_devices() -> Result<(), Vec<(DeviceId, LinuxErrno)>> {
+    let devices = BLOCK_DEVICE.lock().clone();
+    let errors: Vec<_> = devices
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, device)| device.flush().err().map(|e| (block_device_id(idx), e)))
+        .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+//第idx个块设备的设备号
+pub fn block_device_id(idx: usize) -> DeviceId {
+    DeviceClass::VirtioBlock.device_id(idx)
+}
+
+//第idx个块设备在/dev下的名字: vda, vdb, ..., vdz, vdaa, ...
+pub fn block_device_name(idx: usize) -> String {
+    let mut suffix = Vec::new();
+    let mut n = idx;
+    loop {
+        suffix.push(b'a' + (n % 26) as u8);
+        if n < 26 {
+            break;
+        }
+        n = n / 26 - 1;
+    }
+    suffix.reverse();
+    let mut name = String::from("vd");
+    name.push_str(core::str::from_utf8(&suffix).unwrap());
+    name
+}
+
+//块设备ioctl命令
+const BLKFLSBUF: u32 = 0x1261; //写回并丢弃整个设备的页缓存
+const BLKDROPRANGE: u32 = 0x12c8; //自定义, 写回并丢弃arg指向的[偏移, 长度]范围内的缓存, 固定的页保留
+
 //块设备
 pub struct BLKDevice {
     device_id: DeviceId,
@@ -34,6 +91,55 @@ impl BLKDevice {
     }
 }
 
+//带文件位置的块设备, 用于没有显式偏移的顺序读写, 同一个SeekableBlock上的读写共享一个位置
+pub struct SeekableBlock {
+    device: Arc<dyn BlockDevice>,
+    position: AtomicUsize,
+}
+
+impl SeekableBlock {
+    pub fn new(device: Arc<dyn BlockDevice>) -> Self {
+        Self {
+            device,
+            position: AtomicUsize::new(0),
+        }
+    }
+    //当前位置
+    pub fn position(&self) -> usize {
+        self.position.load(Ordering::Acquire)
+    }
+    //设置位置, 超过设备末尾时返回EINVAL
+    pub fn seek(&self, pos: usize) -> AlienResult<usize> {
+        if pos > self.device.size() {
+            return Err(LinuxErrno::EINVAL);
+        }
+        self.position.store(pos, Ordering::Release);
+        Ok(pos)
+    }
+    //从当前位置读取并前移, 到达设备末尾时返回0
+    pub fn read_next(&self, buf: &mut [u8]) -> AlienResult<usize> {
+        let pos = self.position();
+        let len = min(buf.len(), self.device.size().saturating_sub(pos));
+        if len == 0 {
+            return Ok(0);
+        }
+        let n = self.device.read(&mut buf[..len], pos)?;
+        self.position.store(pos + n, Ordering::Release);
+        Ok(n)
+    }
+    //从当前位置写入并前移, 到达设备末尾时返回0
+    pub fn write_next(&self, buf: &[u8]) -> AlienResult<usize> {
+        let pos = self.position();
+        let len = min(buf.len(), self.device.size().saturating_sub(pos));
+        if len == 0 {
+            return Ok(0);
+        }
+        let n = self.device.write(&buf[..len], pos)?;
+        self.position.store(pos + n, Ordering::Release);
+        Ok(n)
+    }
+}
+
 impl VfsFile for BLKDevice {
     //从文件的offset位置开始读取数据到buf中
     fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
@@ -52,8 +158,24 @@ impl VfsFile for BLKDevice {
         unimplemented!()
     }
     // Called by the close(2) system call to flush a file
-    fn ioctl(&self, _cmd: u32, _arg: usize) -> VfsResult<usize> {
-        unimplemented!()
+    fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize> {
+        match cmd {
+            BLKFLSBUF => {
+                self.device.flush().map_err(|_| VfsError::IoError)?;
+                self.device
+                    .invalidate_all()
+                    .map_err(|_| VfsError::IoError)?;
+                Ok(0)
+            }
+            BLKDROPRANGE => {
+                let mut range = [0u64; 2];
+                shim::copy_data_from_task(arg as *const [u64; 2], &mut range);
+                let (offset, len) = (range[0] as usize, range[1] as usize);
+                self.device.advise(offset, len, Advice::DontNeed);
+                Ok(0)
+            }
+            _ => Err(VfsError::Invalid),
+        }
     }
     // Called by the fsync(2) system call.
     fn flush(&self) -> VfsResult<()> {
@@ -61,7 +183,10 @@ impl VfsFile for BLKDevice {
     }
     // Called by the fsync(2) system call.
     fn fsync(&self) -> VfsResult<()> {
-        Ok(())
+        if !self.device.needs_flush() {
+            return Ok(());
+        }
+        self.device.flush().map_err(|_| VfsError::IoError)
     }
 }
 
@@ -75,7 +200,7 @@ impl VfsInode for BLKDevice {
         Ok(VfsFileStat {
             st_rdev: self.device_id.id(),
             st_size: self.device.size() as u64,
-            st_blksize: 512,
+            st_blksize: self.device.sector_size() as u32,
             ..Default::default()
         })
     }
@@ -1,5 +1,6 @@
 use alloc::sync::Arc;
 use constants::DeviceId;
+use core::cmp::min;
 use spin::Once;
 use vfscore::error::VfsError;
 use vfscore::file::VfsFile;
@@ -16,6 +17,53 @@ pub fn init_gpu(gpu: Arc<dyn GpuDevice>) {
     GPU_DEVICE.call_once(|| gpu);
 }
 
+const FBIOGET_VSCREENINFO: u32 = 0x4600;
+// virtio-gpu framebuffers are B8G8R8A8
+const BYTES_PER_PIXEL: u32 = 4;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+struct FbBitfield {
+    offset: u32,
+    length: u32,
+    msb_right: u32,
+}
+
+// struct fb_var_screeninfo
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+struct FbVarScreenInfo {
+    xres: u32,
+    yres: u32,
+    xres_virtual: u32,
+    yres_virtual: u32,
+    xoffset: u32,
+    yoffset: u32,
+    bits_per_pixel: u32,
+    grayscale: u32,
+    red: FbBitfield,
+    green: FbBitfield,
+    blue: FbBitfield,
+    transp: FbBitfield,
+    nonstd: u32,
+    activate: u32,
+    height: u32,
+    width: u32,
+    accel_flags: u32,
+    pixclock: u32,
+    left_margin: u32,
+    right_margin: u32,
+    upper_margin: u32,
+    lower_margin: u32,
+    hsync_len: u32,
+    vsync_len: u32,
+    sync: u32,
+    vmode: u32,
+    rotate: u32,
+    colorspace: u32,
+    reserved: [u32; 4],
+}
+
 pub struct GPUDevice {
     device_id: DeviceId,
     device: Arc<dyn GpuDevice>,
@@ -28,20 +76,60 @@ impl GPUDevice {
     pub fn device_id(&self) -> DeviceId {
         self.device_id
     }
+
+    // length of the visible part of the framebuffer
+    fn framebuffer_len(&self) -> usize {
+        let (width, height) = self.device.resolution();
+        let len = (width * height * BYTES_PER_PIXEL) as usize;
+        min(self.device.get_framebuffer().len(), len)
+    }
 }
 
 impl VfsFile for GPUDevice {
-    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> VfsResult<usize> {
-        Err(VfsError::Invalid)
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let gbuf = &self.device.get_framebuffer()[..self.framebuffer_len()];
+        let offset = min(offset as usize, gbuf.len());
+        let min_len = (gbuf.len() - offset).min(buf.len());
+        buf[..min_len].copy_from_slice(&gbuf[offset..offset + min_len]);
+        Ok(min_len)
     }
     fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
-        let gbuf = self.device.get_framebuffer();
-        let offset = offset as usize;
-        let gbuf_len = gbuf.len();
-        let min_len = (gbuf_len - offset).min(buf.len());
+        let gbuf = &mut self.device.get_framebuffer()[..self.framebuffer_len()];
+        let offset = min(offset as usize, gbuf.len());
+        let min_len = (gbuf.len() - offset).min(buf.len());
         gbuf[offset..offset + min_len].copy_from_slice(&buf[..min_len]);
+        if min_len > 0 {
+            self.device.flush();
+        }
         Ok(min_len)
     }
+    fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize> {
+        match cmd {
+            FBIOGET_VSCREENINFO => {
+                let (width, height) = self.device.resolution();
+                let field = |offset| FbBitfield {
+                    offset,
+                    length: 8,
+                    msb_right: 0,
+                };
+                let info = FbVarScreenInfo {
+                    xres: width,
+                    yres: height,
+                    xres_virtual: width,
+                    yres_virtual: height,
+                    bits_per_pixel: BYTES_PER_PIXEL * 8,
+                    red: field(16),
+                    green: field(8),
+                    blue: field(0),
+                    transp: field(24),
+                    ..Default::default()
+                };
+                shim::copy_data_to_task(&info, arg as *mut FbVarScreenInfo);
+                Ok(0)
+            }
+            _ => Err(VfsError::Invalid),
+        }
+    }
     fn flush(&self) -> VfsResult<()> {
         self.device.flush();
         Ok(())
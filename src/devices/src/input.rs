@@ -31,14 +31,23 @@ impl INPUTDevice {
 }
 
 impl VfsFile for INPUTDevice {
+    // Block until one event arrives, then pack as many pending events as fit.
     fn read_at(&self, _offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
-        if buf.len() != 8 {
+        if buf.len() < 8 {
             return Err(VfsError::Invalid);
         }
-        let buf = unsafe { core::slice::from_raw_parts_mut(buf.as_mut_ptr() as *mut u64, 1) };
-        let event = self.device.read_event_with_block();
-        buf[0] = event;
-        Ok(1)
+        let mut events = buf.chunks_exact_mut(8);
+        let first = events.next().unwrap();
+        first.copy_from_slice(&self.device.read_event_with_block().to_ne_bytes());
+        let mut count = 8;
+        for slot in events {
+            match self.device.read_event_without_block() {
+                Some(event) => slot.copy_from_slice(&event.to_ne_bytes()),
+                None => break,
+            }
+            count += 8;
+        }
+        Ok(count)
     }
     fn write_at(&self, _offset: u64, _buf: &[u8]) -> VfsResult<usize> {
         Err(VfsError::Invalid)
@@ -46,7 +55,7 @@ impl VfsFile for INPUTDevice {
     fn poll(&self, event: VfsPollEvents) -> VfsResult<VfsPollEvents> {
         let mut res = VfsPollEvents::empty();
         if event.contains(VfsPollEvents::IN) {
-            if self.device.is_empty() {
+            if !self.device.is_empty() {
                 res |= VfsPollEvents::IN;
             }
         }
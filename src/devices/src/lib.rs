@@ -3,6 +3,7 @@
 mod block;
 mod gpu;
 mod input;
+mod manager;
 mod net;
 mod prob;
 mod rtc;
@@ -15,8 +16,12 @@ use alloc::sync::Arc;
 
 use crate::prob::Probe;
 use alloc::vec::Vec;
-pub use block::{BLKDevice, BLOCK_DEVICE};
+pub use block::{
+    block_device_id, block_device_name, get_block_device, sync_all_block_devices, BLKDevice,
+    SeekableBlock, BLOCK_DEVICE,
+};
 use config::MAX_INPUT_EVENT_NUM;
+use constants::AlienResult;
 use core::ptr::NonNull;
 use device_interface::{DeviceBase, GpuDevice, LowBlockDevice};
 use drivers::block_device::GenericBlockDevice;
@@ -27,8 +32,9 @@ pub use gpu::{GPUDevice, GPU_DEVICE};
 pub use input::{INPUTDevice, KEYBOARD_INPUT_DEVICE, MOUSE_INPUT_DEVICE};
 use interrupt::register_device_to_plic;
 use log::info;
+pub use manager::{DeviceClass, DeviceIdAllocator, DeviceManager, DEVICE_IDS, DEVICE_MANAGER};
 use platform::println;
-pub use rtc::{RTCDevice, RTC_DEVICE};
+pub use rtc::{get_rtc_time, RTCDevice, RTC_DEVICE};
 pub use uart::{UARTDevice, UART_DEVICE};
 use virtio_drivers::transport::mmio::{MmioTransport, VirtIOHeader};
 use virtio_drivers::transport::{DeviceType, Transport};
@@ -73,7 +79,7 @@ pub fn init_device() {
 
     #[cfg(feature = "vf2")]
     match dtb.probe_sdio() {
-        Some(sdio) => init_block_device(sdio, None),
+        Some(sdio) => init_block_device(sdio, None).expect("Init SDIO block device failed"),
         None => {
             panic!("There is no sdio device");
         }
@@ -88,7 +94,8 @@ pub fn init_device() {
             alloc::string::String::new(),
         ),
         None,
-    );
+    )
+    .expect("Init block device failed");
 
     #[cfg(any(feature = "hifive", feature = "vf2"))]
     init_net(None);
@@ -105,6 +112,7 @@ fn init_rtc(rtc: prob::DeviceInfo) {
             let rtc = Arc::new(GoldFishRtc::new(info.base_addr));
             let current_time = rtc.read_time_string();
             rtc::init_rtc(rtc.clone());
+            DEVICE_MANAGER.register(DEVICE_IDS.alloc(DeviceClass::Rtc), rtc.clone());
             register_device_to_plic(info.irq, rtc);
             println!("Init rtc success, current time: {:?}", current_time);
         }
@@ -114,22 +122,28 @@ fn init_rtc(rtc: prob::DeviceInfo) {
     }
 }
 
+// uart input clocks, used to compute the baud rate divisor
+const QEMU_UART_CLOCK: u32 = 3_686_400;
+const VF2_UART_CLOCK: u32 = 24_000_000;
+
 fn init_uart(uart: prob::DeviceInfo) {
     let (base_addr, irq) = (uart.base_addr, uart.irq);
     println!("Init uart, base_addr:{:#x},irq:{}", base_addr, irq);
     match uart.compatible.as_str() {
         "ns16550a" => {
             // qemu
-            let uart = Uart16550::new(base_addr);
+            let uart = Uart16550::new(base_addr).with_clock(QEMU_UART_CLOCK);
             let uart = Arc::new(Uart::new(Box::new(uart)));
             uart::init_uart(uart.clone());
+            DEVICE_MANAGER.register(DEVICE_IDS.alloc(DeviceClass::Serial), uart.clone());
             register_device_to_plic(irq, uart);
         }
         "snps,dw-apb-uart" => {
             // vf2
-            let uart = Uart8250::new(base_addr);
+            let uart = Uart8250::new(base_addr).with_clock(VF2_UART_CLOCK);
             let uart = Arc::new(Uart::new(Box::new(uart)));
             uart::init_uart(uart.clone());
+            DEVICE_MANAGER.register(DEVICE_IDS.alloc(DeviceClass::Serial), uart.clone());
             register_device_to_plic(irq, uart);
         }
         name => {
@@ -167,7 +181,11 @@ pub fn init_virtio_mmio(devices: Vec<prob::DeviceInfo>) {
                             init_input_device(device, "mouse", Some(transport));
                         }
                     }
-                    DeviceType::Block => init_block_device(device, Some(transport)),
+                    DeviceType::Block => {
+                        if let Err(err) = init_block_device(device, Some(transport)) {
+                            println!("Init block device failed: {:?}", err);
+                        }
+                    }
                     DeviceType::GPU => init_gpu(device, Some(transport)),
                     DeviceType::Network => init_net(Some(device)),
                     ty => {
@@ -189,7 +207,21 @@ pub fn checkout_fs_img() {
     println!("img_start: {:#x}, img_size: {:#x}", img_start, img_size);
 }
 
-fn init_block_device(blk: prob::DeviceInfo, mmio_transport: Option<MmioTransport>) {
+fn print_block_device_size(block_device: &dyn LowBlockDevice) {
+    match block_device.capacity() {
+        Ok(size) => println!(
+            "Block device size is {}MB",
+            size * block_device.block_size() / 1024 / 1024
+        ),
+        Err(_) => println!("Block device size is unknown"),
+    }
+}
+
+// a device that cannot report its capacity is not registered, the error goes to the caller
+fn init_block_device(
+    blk: prob::DeviceInfo,
+    mmio_transport: Option<MmioTransport>,
+) -> AlienResult<()> {
     use drivers::block_device::VirtIOBlkWrapper;
     let (base_addr, irq) = (blk.base_addr, blk.irq);
     match blk.compatible.as_str() {
@@ -198,12 +230,11 @@ fn init_block_device(blk: prob::DeviceInfo, mmio_transport: Option<MmioTransport
             // let mut block_device = VirtIOBlkWrapper::new(blk.base_addr);
             let block_device = VirtIOBlkWrapper::from_mmio(mmio_transport.unwrap());
             println!("Init block device, base_addr:{:#x},irq:{}", base_addr, irq);
-            let size = block_device.capacity();
-            println!("Block device size is {}MB", size * 512 / 1024 / 1024);
-            let block_device = Arc::new(GenericBlockDevice::new(Box::new(block_device)));
-            block::init_block_device(block_device);
+            print_block_device_size(&block_device);
+            let block_device = Arc::new(GenericBlockDevice::new(Box::new(block_device))?);
+            let idx = block::init_block_device(block_device);
             // register_device_to_plic(irq, block_device);
-            println!("Init block device success");
+            println!("Init block device {} success", block_device_name(idx));
         }
         "starfive,jh7110-sdio" => {
             // starfive2
@@ -219,40 +250,41 @@ fn init_block_device(blk: prob::DeviceInfo, mmio_transport: Option<MmioTransport
                 }
                 use drivers::block_device::{VF2SDDriver, Vf2SdDriver};
                 let block_device = VF2SDDriver::new(Vf2SdDriver::new(sleep));
-                let size = block_device.capacity();
-                println!("Block device size is {}MB", size * 512 / 1024 / 1024);
-                let block_device = Arc::new(GenericBlockDevice::new(Box::new(block_device)));
+                print_block_device_size(&block_device);
+                let block_device = Arc::new(GenericBlockDevice::new(Box::new(block_device))?);
                 block::init_block_device(block_device);
                 // register_device_to_plic(irq, block_device);
                 println!("Init SDIO block device success");
             }
             #[cfg(feature = "ramdisk")]
             {
-                init_ramdisk();
+                init_ramdisk()?;
             }
         }
         name => {
             println!("Don't support block device: {}", name);
             #[cfg(feature = "ramdisk")]
             {
-                init_ramdisk();
+                init_ramdisk()?;
             }
             #[cfg(not(feature = "ramdisk"))]
             panic!("System need block device, but there is no block device");
         }
     }
+    Ok(())
 }
 
 #[cfg(feature = "ramdisk")]
-fn init_ramdisk() {
+fn init_ramdisk() -> AlienResult<()> {
     use drivers::block_device::MemoryFat32Img;
     checkout_fs_img();
     let data =
         unsafe { core::slice::from_raw_parts_mut(RAMDISK.as_ptr() as *mut u8, RAMDISK.len()) };
-    let block_device = GenericBlockDevice::new(Box::new(MemoryFat32Img::new(data)));
+    let block_device = GenericBlockDevice::new(Box::new(MemoryFat32Img::new(data)))?;
     let block_device = Arc::new(block_device);
     block::init_block_device(block_device);
     println!("Init fake block device success");
+    Ok(())
 }
 
 fn init_gpu(gpu: prob::DeviceInfo, mmio_transport: Option<MmioTransport>) {
@@ -266,7 +298,8 @@ fn init_gpu(gpu: prob::DeviceInfo, mmio_transport: Option<MmioTransport>) {
             let resolution = gpu.resolution();
             println!("GPU resolution: {:?}", resolution);
             let gpu = Arc::new(gpu);
-            gpu::init_gpu(gpu);
+            gpu::init_gpu(gpu.clone());
+            DEVICE_MANAGER.register(DEVICE_IDS.alloc(DeviceClass::Framebuffer), gpu);
             // let _ = register_device_to_plic(irq, gpu);
             println!("Init gpu success");
         }
@@ -294,6 +327,7 @@ fn init_input_device(input: prob::DeviceInfo, name: &str, mmio_transport: Option
                 "keyboard" => input::init_keyboard_input_device(input.clone()),
                 _ => panic!("Don't support {} input device", name),
             }
+            DEVICE_MANAGER.register(DEVICE_IDS.alloc(DeviceClass::Input), input.clone());
             let _ = register_device_to_plic(irq, input);
             println!("Init keyboard input device success");
         }
@@ -0,0 +1,112 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use constants::DeviceId;
+use core::any::Any;
+use device_interface::DeviceBase;
+use ksync::Mutex;
+
+pub static DEVICE_MANAGER: DeviceManager = DeviceManager::new();
+pub static DEVICE_IDS: DeviceIdAllocator = DeviceIdAllocator::new();
+
+//所有已初始化设备的注册表, 以设备号为键
+pub struct DeviceManager {
+    devices: Mutex<BTreeMap<DeviceId, Arc<dyn DeviceBase>>>,
+}
+
+impl DeviceManager {
+    pub const fn new() -> Self {
+        Self {
+            devices: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    //注册设备, 设备号已存在时替换旧设备
+    pub fn register(&self, id: DeviceId, device: Arc<dyn DeviceBase>) {
+        self.devices.lock().insert(id, device);
+    }
+
+    //按设备号查找设备
+    pub fn get(&self, id: DeviceId) -> Option<Arc<dyn DeviceBase>> {
+        self.devices.lock().get(&id).cloned()
+    }
+
+    //按设备号查找设备, 并转换为具体类型T, 设备不存在或类型不符时返回None
+    pub fn get_as<T: DeviceBase + Any>(&self, id: DeviceId) -> Option<Arc<T>> {
+        self.get(id)?.downcast_arc()
+    }
+
+    //按设备号顺序遍历当前已注册的设备
+    pub fn iter(&self) -> impl Iterator<Item = (DeviceId, Arc<dyn DeviceBase>)> {
+        let devices: Vec<_> = self
+            .devices
+            .lock()
+            .iter()
+            .map(|(id, device)| (*id, device.clone()))
+            .collect();
+        devices.into_iter()
+    }
+}
+
+impl Default for DeviceManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//按Linux主次设备号约定分配设备号的设备类别
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DeviceClass {
+    VirtioBlock, //virtio块设备, 每个磁盘占16个次设备号, 留给分区
+    Serial,      //串口ttyS
+    Rtc,         //misc设备中的rtc
+    Framebuffer, //帧缓冲fb
+    Input,       //输入设备event
+}
+
+impl DeviceClass {
+    //(主设备号, 第一个设备的次设备号, 每个设备占用的次设备号数)
+    const fn layout(self) -> (u32, u32, u32) {
+        match self {
+            DeviceClass::VirtioBlock => (254, 0, 16),
+            DeviceClass::Serial => (4, 64, 1),
+            DeviceClass::Rtc => (10, 135, 1),
+            DeviceClass::Framebuffer => (29, 0, 1),
+            DeviceClass::Input => (13, 64, 1),
+        }
+    }
+
+    //该类别第idx个设备的设备号
+    pub fn device_id(self, idx: usize) -> DeviceId {
+        let (major, first_minor, minors) = self.layout();
+        DeviceId::new(major, first_minor + idx as u32 * minors)
+    }
+}
+
+//设备号分配器, 每个类别按顺序分配, 同一类别的设备号不会重复
+pub struct DeviceIdAllocator {
+    allocated: Mutex<BTreeMap<DeviceClass, usize>>, //类别 -> 已分配的设备数
+}
+
+impl DeviceIdAllocator {
+    pub const fn new() -> Self {
+        Self {
+            allocated: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    //为该类别的下一个设备分配设备号
+    pub fn alloc(&self, class: DeviceClass) -> DeviceId {
+        let mut allocated = self.allocated.lock();
+        let count = allocated.entry(class).or_insert(0);
+        let id = class.device_id(*count);
+        *count += 1;
+        id
+    }
+}
+
+impl Default for DeviceIdAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -14,7 +14,6 @@ use vfscore::VfsResult;
 
 pub static RTC_DEVICE: Once<Arc<dyn RtcDevice>> = Once::new();
 
-#[allow(unused)]
 pub fn get_rtc_time() -> Option<RtcTime> {
     RTC_DEVICE.get().map(|rtc| rtc.read_time())
 }
@@ -1,9 +1,12 @@
+use alloc::collections::VecDeque;
 use alloc::sync::Arc;
+use config::UART_INPUT_BUF_SIZE;
 use constants::io::{LocalModes, TeletypeCommand, Termios, WinSize};
 use constants::DeviceId;
-use device_interface::UartDevice;
+use device_interface::{DeviceBase, UartDevice};
 use ksync::Mutex;
 use spin::Once;
+use timer::read_timer;
 use vfscore::error::VfsError;
 use vfscore::file::VfsFile;
 use vfscore::inode::{InodeAttr, VfsInode};
@@ -17,17 +20,129 @@ pub fn init_uart(uart: Arc<dyn UartDevice>) {
     UART_DEVICE.call_once(|| uart);
 }
 
+// termios input flags and c_cc indices
+const INLCR: u32 = 0o000100;
+const IGNCR: u32 = 0o000200;
+const ICRNL: u32 = 0o000400;
+const IXON: u32 = 0o002000;
+const VSTART: usize = 8;
+const VSTOP: usize = 9;
+
+// sent to the foreground process group when the window size changes
+const SIGWINCH: u32 = 28;
+
+// termios c_cflag baud rate bits, BOTHER means the rate is in ispeed/ospeed
+const CBAUD: u32 = 0o010017;
+const CBAUDEX: u32 = 0o010000;
+const BOTHER: u32 = 0o010000;
+// rates of the standard speed codes, B0..B38400 are 0..=15,
+// B57600..B4000000 are CBAUDEX | 1..=15
+const BAUD_RATES: [u32; 16] = [
+    0, 50, 75, 110, 134, 150, 200, 300, 600, 1200, 1800, 2400, 4800, 9600, 19200, 38400,
+];
+const BAUD_RATES_EX: [u32; 15] = [
+    57600, 115200, 230400, 460800, 500000, 576000, 921600, 1000000, 1152000, 1500000, 2000000,
+    2500000, 3000000, 3500000, 4000000,
+];
+
+// CR/NL handling on input, None means the byte is dropped.
+// The default termios has ICRNL set, so Enter ends a canonical line
+fn translate_input(iflag: u32, ch: u8) -> Option<u8> {
+    match ch {
+        b'\r' if iflag & IGNCR != 0 => None,
+        b'\r' if iflag & ICRNL != 0 => Some(b'\n'),
+        b'\n' if iflag & INLCR != 0 => Some(b'\r'),
+        _ => Some(ch),
+    }
+}
+
+// WinSize has no PartialEq, compare it as plain bytes
+fn same_winsize(a: &WinSize, b: &WinSize) -> bool {
+    let size = core::mem::size_of::<WinSize>();
+    let a = unsafe { core::slice::from_raw_parts(a as *const WinSize as *const u8, size) };
+    let b = unsafe { core::slice::from_raw_parts(b as *const WinSize as *const u8, size) };
+    a == b
+}
+
+// output baud rate set in termios
+fn termios_baud(termios: &Termios) -> u32 {
+    let code = termios.cflag & CBAUD;
+    if code == BOTHER {
+        termios.ospeed
+    } else if code & CBAUDEX != 0 {
+        BAUD_RATES_EX[(code & !CBAUDEX) as usize - 1]
+    } else {
+        BAUD_RATES[code as usize]
+    }
+}
+
+// store the rate in termios, using a standard code when it is within 2% of one
+fn set_termios_baud(termios: &mut Termios, baud: u32) {
+    let close = |rate: u32| rate.abs_diff(baud) <= rate / 50;
+    let code = if let Some(i) = BAUD_RATES.iter().position(|&rate| close(rate)) {
+        i as u32
+    } else if let Some(i) = BAUD_RATES_EX.iter().position(|&rate| close(rate)) {
+        CBAUDEX | (i as u32 + 1)
+    } else {
+        BOTHER
+    };
+    termios.cflag = (termios.cflag & !CBAUD) | code;
+    termios.ispeed = baud;
+    termios.ospeed = baud;
+}
+
 #[derive(Debug, Default)]
 pub struct IoData {
     foreground_pgid: u32,
     winsize: WinSize,
     termios: Termios,
+    // output is paused by VSTOP until VSTART arrives
+    flow_stopped: bool,
+    // pass bytes through untouched while ICANON is off
+    raw_passthrough: bool,
+}
+
+impl IoData {
+    // no translation, echo or control characters at all
+    fn is_raw(&self) -> bool {
+        self.raw_passthrough
+            && !LocalModes::from_bits_truncate(self.termios.lflag).contains(LocalModes::ICANON)
+    }
+}
+
+// what to do with a new byte when the input buffer is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    DropOldest,
+    DropNewest,
+}
+
+struct InputBuffer {
+    buf: VecDeque<u8>,
+    policy: OverflowPolicy,
+    dropped: usize,
+}
+
+impl InputBuffer {
+    fn push(&mut self, ch: u8) {
+        if self.buf.len() >= UART_INPUT_BUF_SIZE {
+            self.dropped += 1;
+            match self.policy {
+                OverflowPolicy::DropOldest => {
+                    self.buf.pop_front();
+                }
+                OverflowPolicy::DropNewest => return,
+            }
+        }
+        self.buf.push_back(ch);
+    }
 }
 
 pub struct UARTDevice {
     device_id: DeviceId,
     device: Arc<dyn UartDevice>,
     io: Mutex<IoData>,
+    input: Mutex<InputBuffer>,
 }
 
 impl UARTDevice {
@@ -36,51 +151,220 @@ impl UARTDevice {
             device_id,
             device,
             io: Mutex::new(IoData::default()),
+            input: Mutex::new(InputBuffer {
+                buf: VecDeque::new(),
+                policy: OverflowPolicy::DropNewest,
+                dropped: 0,
+            }),
         }
     }
+
+    pub fn with_overflow_policy(self, policy: OverflowPolicy) -> Self {
+        self.input.lock().policy = policy;
+        self
+    }
+
     pub fn device_id(&self) -> DeviceId {
         self.device_id
     }
+
+    // with ICANON off, make read_at return the input verbatim for binary protocols
+    pub fn set_raw_passthrough(&self, enable: bool) {
+        self.io.lock().raw_passthrough = enable;
+    }
+
+    // number of input bytes dropped because the buffer was full
+    pub fn dropped_bytes(&self) -> usize {
+        self.input.lock().dropped
+    }
+
+    // wait until input arrives or timer::read_timer() reaches deadline, then return
+    // whatever has been received, zero bytes if the deadline passed first.
+    // CR/NL translation follows termios like read_at, there is no echo or line editing.
+    // Like read_at the task yields and polls the hardware between checks
+    pub fn read_timeout(&self, buf: &mut [u8], deadline: usize) -> usize {
+        if buf.is_empty() {
+            return 0;
+        }
+        loop {
+            self.fill_input();
+            let (iflag, raw) = {
+                let io = self.io.lock();
+                (io.termios.iflag, io.is_raw())
+            };
+            let mut input = self.input.lock();
+            let mut count = 0;
+            while count < buf.len() {
+                let ch = match input.buf.pop_front() {
+                    Some(ch) => ch,
+                    None => break,
+                };
+                let ch = if raw {
+                    Some(ch)
+                } else {
+                    translate_input(iflag, ch)
+                };
+                if let Some(ch) = ch {
+                    buf[count] = ch;
+                    count += 1;
+                }
+            }
+            drop(input);
+            if count > 0 || read_timer() >= deadline {
+                return count;
+            }
+            shim::suspend();
+        }
+    }
+
+    // queue bytes as if they had been received, for scripted input and tests.
+    // Only the software buffer is touched, the same overflow policy applies
+    pub fn push_input(&self, bytes: &[u8]) {
+        let mut input = self.input.lock();
+        for &ch in bytes {
+            input.push(ch);
+        }
+    }
+
+    // move everything the hardware has into the input buffer
+    fn fill_input(&self) {
+        let mut input = self.input.lock();
+        while self.device.have_data_to_get() {
+            let ch = self.device.get().unwrap();
+            if !self.handle_flow_control(ch) {
+                input.push(ch);
+            }
+        }
+    }
+
+    // take one byte from the input buffer, yield and poll the hardware while it is empty.
+    // The raw uart rather than this device is registered to the plic, so the task can't
+    // sleep until hand_irq runs
+    fn getc(&self) -> u8 {
+        loop {
+            if let Some(ch) = self.try_getc() {
+                return ch;
+            }
+            shim::suspend();
+        }
+    }
+
+    // wait until everything written so far has left the uart
+    fn drain_output(&self) {
+        while !self.device.tx_empty() {
+            shim::suspend();
+        }
+    }
+
+    // drop input that has been received but not read yet
+    fn discard_input(&self) {
+        self.fill_input();
+        self.input.lock().buf.clear();
+    }
+
+    // take one byte only if it is already there
+    fn try_getc(&self) -> Option<u8> {
+        self.fill_input();
+        self.input.lock().buf.pop_front()
+    }
+
+    // handle VSTOP/VSTART when IXON is set, return true if the byte was consumed
+    fn handle_flow_control(&self, ch: u8) -> bool {
+        let mut io = self.io.lock();
+        if io.termios.iflag & IXON == 0 || io.is_raw() {
+            return false;
+        }
+        // a zero control character means the function is disabled
+        if ch != 0 && ch == io.termios.cc[VSTOP] {
+            io.flow_stopped = true;
+            true
+        } else if ch != 0 && ch == io.termios.cc[VSTART] {
+            io.flow_stopped = false;
+            true
+        } else {
+            false
+        }
+    }
 }
 
 impl VfsFile for UARTDevice {
     fn read_at(&self, _offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
-        // read util \r and transform to \n
+        if self.io.lock().is_raw() {
+            // wait for the first byte, then return whatever else has arrived
+            if buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.getc();
+            let mut read_count = 1;
+            while read_count < buf.len() {
+                match self.try_getc() {
+                    Some(ch) => buf[read_count] = ch,
+                    None => break,
+                }
+                read_count += 1;
+            }
+            return Ok(read_count);
+        }
+        // read until \n after CR/NL translation
         let mut read_count = 0;
         loop {
-            let ch = self.device.get();
-            assert!(ch.is_some());
-            let ch = ch.unwrap();
+            let ch = self.getc();
+            let (iflag, echo) = {
+                let io = self.io.lock();
+                let lflag = LocalModes::from_bits_truncate(io.termios.lflag);
+                (io.termios.iflag, lflag.contains(LocalModes::ECHO))
+            };
+            let ch = match translate_input(iflag, ch) {
+                Some(ch) => ch,
+                None => continue,
+            };
             buf[read_count] = ch;
             read_count += 1;
-            if ch == b'\r' {
-                buf[read_count - 1] = b'\n';
-                if LocalModes::from_bits_truncate(self.io.lock().termios.lflag)
-                    .contains(LocalModes::ECHO)
-                {
-                    self.device.put(b'\n');
-                }
-                break;
-            }
-            if LocalModes::from_bits_truncate(self.io.lock().termios.lflag)
-                .contains(LocalModes::ECHO)
-            {
+            if echo {
                 self.device.put(ch);
             }
-            if read_count >= buf.len() {
+            if ch == b'\n' || read_count >= buf.len() {
                 break;
             }
         }
         Ok(read_count)
     }
     fn write_at(&self, _offset: u64, buf: &[u8]) -> VfsResult<usize> {
-        self.device.put_bytes(buf);
-        Ok(buf.len())
+        // wait until the terminal sends VSTART, which only fill_input consumes,
+        // so keep receiving while stopped even if no reader is running
+        while self.io.lock().flow_stopped {
+            self.fill_input();
+            if !self.io.lock().flow_stopped {
+                break;
+            }
+            shim::suspend();
+        }
+        // wait for room for at least one byte, then write the run the uart accepts in one
+        // call. put_bytes sends '\n' as "\r\n", so a newline takes two bytes of room
+        let space = loop {
+            let space = self.device.space_to_put();
+            if space > 0 {
+                break space;
+            }
+            shim::suspend();
+        };
+        let mut used = 0;
+        let mut count = 0;
+        for &c in buf {
+            let need = if c == b'\n' { 2 } else { 1 };
+            if count > 0 && used + need > space {
+                break;
+            }
+            used += need;
+            count += 1;
+        }
+        self.device.put_bytes(&buf[..count]);
+        Ok(count)
     }
     fn poll(&self, event: VfsPollEvents) -> VfsResult<VfsPollEvents> {
         let mut res = VfsPollEvents::empty();
         if event.contains(VfsPollEvents::IN) {
-            if self.device.have_data_to_get() {
+            if !self.input.lock().buf.is_empty() || self.device.have_data_to_get() {
                 res |= VfsPollEvents::IN;
             }
         }
@@ -92,15 +376,35 @@ impl VfsFile for UARTDevice {
         Ok(res)
     }
     fn ioctl(&self, cmd: u32, arg: usize) -> VfsResult<usize> {
-        let mut io = self.io.lock();
         let cmd = TeletypeCommand::try_from(cmd).unwrap();
+        // TCSETSW waits for pending output before applying, TCSETSF also drops unread input.
+        // Done before taking io since hand_irq needs it to keep receiving
+        if matches!(cmd, TeletypeCommand::TCSETSW | TeletypeCommand::TCSETSF) {
+            self.drain_output();
+        }
+        if matches!(cmd, TeletypeCommand::TCSETSF) {
+            self.discard_input();
+        }
+        let mut io = self.io.lock();
         return match cmd {
             TeletypeCommand::TCGETS | TeletypeCommand::TCGETA => {
                 shim::copy_data_to_task(&io.termios, arg as *mut Termios);
                 Ok(0)
             }
             TeletypeCommand::TCSETS | TeletypeCommand::TCSETSW | TeletypeCommand::TCSETSF => {
+                let old_baud = termios_baud(&io.termios);
                 shim::copy_data_from_task(arg as *const Termios, &mut io.termios);
+                if io.termios.iflag & IXON == 0 {
+                    io.flow_stopped = false;
+                }
+                // B0 hangs up the line, keep the current divisor
+                let baud = termios_baud(&io.termios);
+                if baud != 0 && baud != old_baud {
+                    // report the rate the hardware really uses in later TCGETS
+                    if let Some(actual) = self.device.set_baud(baud) {
+                        set_termios_baud(&mut io.termios, actual);
+                    }
+                }
                 Ok(0)
             }
             TeletypeCommand::TIOCGPGRP => {
@@ -118,7 +422,14 @@ impl VfsFile for UARTDevice {
                 Ok(0)
             }
             TeletypeCommand::TIOCSWINSZ => {
-                shim::copy_data_from_task(arg as *const WinSize, &mut io.winsize);
+                let mut winsize = WinSize::default();
+                shim::copy_data_from_task(arg as *const WinSize, &mut winsize);
+                if !same_winsize(&winsize, &io.winsize) {
+                    io.winsize = winsize;
+                    if io.foreground_pgid != 0 {
+                        shim::kill_pgrp(io.foreground_pgid, SIGWINCH);
+                    }
+                }
                 Ok(0)
             }
             _ => {
@@ -134,6 +445,15 @@ impl VfsFile for UARTDevice {
     }
 }
 
+// Moves received bytes into the input buffer when this device is registered
+// to the plic, readers poll as well so they don't depend on it
+impl DeviceBase for UARTDevice {
+    fn hand_irq(&self) {
+        self.device.hand_irq();
+        self.fill_input();
+    }
+}
+
 impl VfsInode for UARTDevice {
     fn get_super_block(&self) -> VfsResult<Arc<dyn VfsSuperBlock>> {
         Err(VfsError::NoSys)
@@ -154,3 +474,92 @@ impl VfsInode for UARTDevice {
         VfsNodeType::CharDevice
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    // uart with a fixed amount of transmit room that records each put_bytes call
+    struct SmallFifo {
+        space: usize,
+        writes: Mutex<Vec<Vec<u8>>>,
+    }
+
+    impl UartDevice for SmallFifo {
+        fn put(&self, c: u8) {
+            self.put_bytes(&[c]);
+        }
+
+        fn get(&self) -> Option<u8> {
+            None
+        }
+
+        fn put_bytes(&self, bytes: &[u8]) {
+            self.writes.lock().push(bytes.to_vec());
+        }
+
+        fn have_data_to_get(&self) -> bool {
+            false
+        }
+
+        fn have_space_to_put(&self) -> bool {
+            self.space > 0
+        }
+
+        fn space_to_put(&self) -> usize {
+            self.space
+        }
+    }
+
+    impl DeviceBase for SmallFifo {
+        fn hand_irq(&self) {}
+    }
+
+    // write_at takes what fits in one put_bytes call, counting '\n' as two bytes
+    #[test]
+    fn write_at_returns_short_count() {
+        let fifo = Arc::new(SmallFifo {
+            space: 5,
+            writes: Mutex::new(Vec::new()),
+        });
+        let uart = UARTDevice::new(DeviceId::new(4, 0), fifo.clone());
+        assert_eq!(uart.write_at(0, b"ab\ncdef").unwrap(), 4);
+        assert_eq!(uart.write_at(0, b"\n\n\n").unwrap(), 2);
+        assert_eq!(uart.write_at(0, b"abcdefgh").unwrap(), 5);
+        let writes = fifo.writes.lock();
+        let expected: [&[u8]; 3] = [b"ab\nc", b"\n\n", b"abcde"];
+        assert_eq!(*writes, expected);
+    }
+
+    #[test]
+    fn translate_input_table() {
+        // (iflag, input, expected)
+        let cases = [
+            (0, b'\r', Some(b'\r')),
+            (0, b'\n', Some(b'\n')),
+            (0, b'a', Some(b'a')),
+            (ICRNL, b'\r', Some(b'\n')),
+            (ICRNL, b'\n', Some(b'\n')),
+            (IGNCR, b'\r', None),
+            (IGNCR, b'\n', Some(b'\n')),
+            // IGNCR takes precedence over ICRNL
+            (IGNCR | ICRNL, b'\r', None),
+            (INLCR, b'\n', Some(b'\r')),
+            (INLCR, b'\r', Some(b'\r')),
+            // both directions at once swap CR and NL
+            (INLCR | ICRNL, b'\r', Some(b'\n')),
+            (INLCR | ICRNL, b'\n', Some(b'\r')),
+            (INLCR | IGNCR | ICRNL, b'x', Some(b'x')),
+        ];
+        for &(iflag, ch, expected) in cases.iter() {
+            assert_eq!(
+                translate_input(iflag, ch),
+                expected,
+                "iflag {:#o} input {:#x}",
+                iflag,
+                ch
+            );
+        }
+    }
+}
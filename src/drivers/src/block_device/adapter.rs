@@ -0,0 +1,252 @@
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use constants::{AlienResult, LinuxErrno};
+use device_interface::{BlockDevice, BlockDeviceInfo, BlockHealth, LowBlockDevice};
+
+//把字节寻址的BlockDevice当作LowBlockDevice使用, 例如在分区上再叠加一层GenericBlockDevice缓存.
+//块大小和inner的扇区大小相同. inner本身也可以是GenericBlockDevice, 从而组成两级缓存:
+//上层缓存的flush先把脏页写入下层, 再刷新下层, 下层再写回到最终的设备
+pub struct BlockAsLow {
+    inner: Arc<dyn BlockDevice>,
+    base_offset: usize, //第0块在inner中的字节偏移
+    block_size: usize,  //inner的扇区大小
+}
+
+impl BlockAsLow {
+    //构造函数, base_offset必须按inner的扇区对齐
+    pub fn new(inner: Arc<dyn BlockDevice>, base_offset: usize) -> Self {
+        let block_size = inner.sector_size();
+        assert!(
+            base_offset.is_multiple_of(block_size),
+            "base offset is not sector aligned"
+        );
+        Self {
+            inner,
+            base_offset,
+            block_size,
+        }
+    }
+
+    //块在inner中的字节偏移
+    fn offset(&self, block_id: usize) -> usize {
+        self.base_offset + block_id * self.block_size
+    }
+}
+
+impl LowBlockDevice for BlockAsLow {
+    fn read_block(&mut self, block_id: usize, buf: &mut [u8]) -> AlienResult<()> {
+        if buf.len() != self.block_size {
+            return Err(LinuxErrno::EINVAL);
+        }
+        self.read_blocks(block_id, buf)
+    }
+
+    fn write_block(&mut self, block_id: usize, buf: &[u8]) -> AlienResult<()> {
+        if buf.len() != self.block_size {
+            return Err(LinuxErrno::EINVAL);
+        }
+        self.write_blocks(block_id, buf)
+    }
+
+    //多块请求合并为inner上的一次读写
+    fn read_blocks(&mut self, block_id: usize, buf: &mut [u8]) -> AlienResult<()> {
+        if !buf.len().is_multiple_of(self.block_size) {
+            return Err(LinuxErrno::EINVAL);
+        }
+        //读到设备末尾之外
+        if self.inner.read(buf, self.offset(block_id))? != buf.len() {
+            return Err(LinuxErrno::EIO);
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, block_id: usize, buf: &[u8]) -> AlienResult<()> {
+        if !buf.len().is_multiple_of(self.block_size) {
+            return Err(LinuxErrno::EINVAL);
+        }
+        if self.inner.write(buf, self.offset(block_id))? != buf.len() {
+            return Err(LinuxErrno::EIO);
+        }
+        Ok(())
+    }
+
+    fn capacity(&self) -> AlienResult<usize> {
+        Ok(self.inner.size().saturating_sub(self.base_offset) / self.block_size)
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn optimal_io_size(&self) -> usize {
+        self.inner.optimal_io_size()
+    }
+
+    fn flush(&mut self) -> AlienResult<()> {
+        self.inner.flush()
+    }
+
+    fn needs_flush(&self) -> bool {
+        self.inner.needs_flush()
+    }
+
+    fn health(&self) -> BlockHealth {
+        self.inner.health()
+    }
+
+    fn write_zeroes(&mut self, block_id: usize, count: usize) -> AlienResult<()> {
+        self.inner
+            .write_zeroes(self.offset(block_id), count * self.block_size)
+    }
+}
+
+//只暴露底层设备[start, start+count)扇区的窗口, 块号加上start后转发
+pub(super) struct WindowLow {
+    inner: Box<dyn LowBlockDevice>,
+    start: usize,
+    count: usize,
+}
+
+impl WindowLow {
+    pub fn new(inner: Box<dyn LowBlockDevice>, start: usize, count: usize) -> Self {
+        if let Ok(capacity) = inner.capacity() {
+            assert!(
+                matches!(start.checked_add(count), Some(end) if end <= capacity),
+                "window exceeds device capacity"
+            );
+        }
+        Self {
+            inner,
+            start,
+            count,
+        }
+    }
+
+    //检查[block_id, block_id+count)在窗口内
+    fn check(&self, block_id: usize, count: usize) -> AlienResult<()> {
+        match block_id.checked_add(count) {
+            Some(end) if end <= self.count => Ok(()),
+            _ => Err(LinuxErrno::EINVAL),
+        }
+    }
+}
+
+impl LowBlockDevice for WindowLow {
+    fn read_block(&mut self, block_id: usize, buf: &mut [u8]) -> AlienResult<()> {
+        self.check(block_id, 1)?;
+        self.inner.read_block(self.start + block_id, buf)
+    }
+
+    fn write_block(&mut self, block_id: usize, buf: &[u8]) -> AlienResult<()> {
+        self.check(block_id, 1)?;
+        self.inner.write_block(self.start + block_id, buf)
+    }
+
+    fn read_blocks(&mut self, block_id: usize, buf: &mut [u8]) -> AlienResult<()> {
+        self.check(block_id, buf.len() / self.inner.block_size())?;
+        self.inner.read_blocks(self.start + block_id, buf)
+    }
+
+    fn write_blocks(&mut self, block_id: usize, buf: &[u8]) -> AlienResult<()> {
+        self.check(block_id, buf.len() / self.inner.block_size())?;
+        self.inner.write_blocks(self.start + block_id, buf)
+    }
+
+    fn capacity(&self) -> AlienResult<usize> {
+        Ok(self.count)
+    }
+
+    fn block_size(&self) -> usize {
+        self.inner.block_size()
+    }
+
+    fn optimal_io_size(&self) -> usize {
+        self.inner.optimal_io_size()
+    }
+
+    fn flush(&mut self) -> AlienResult<()> {
+        self.inner.flush()
+    }
+
+    fn needs_flush(&self) -> bool {
+        self.inner.needs_flush()
+    }
+
+    fn health(&self) -> BlockHealth {
+        self.inner.health()
+    }
+
+    fn read_only(&self) -> bool {
+        self.inner.read_only()
+    }
+
+    fn ack_interrupt(&mut self) {
+        self.inner.ack_interrupt()
+    }
+
+    fn write_zeroes(&mut self, block_id: usize, count: usize) -> AlienResult<()> {
+        self.check(block_id, count)?;
+        self.inner.write_zeroes(self.start + block_id, count)
+    }
+
+    fn info(&self) -> BlockDeviceInfo {
+        BlockDeviceInfo {
+            total_sectors: self.count,
+            ..self.inner.info()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{GenericBlockDevice, RamDisk};
+    use super::*;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    //4页的上层缓存叠加在64页的下层缓存上, 下层是内存盘
+    fn two_tier() -> (Arc<GenericBlockDevice>, GenericBlockDevice) {
+        let lower = GenericBlockDevice::new(Box::new(RamDisk::new(256)))
+            .unwrap()
+            .with_cache_frames(64);
+        let lower = Arc::new(lower);
+        let upper = GenericBlockDevice::new(Box::new(BlockAsLow::new(lower.clone(), 0)))
+            .unwrap()
+            .with_cache_frames(4);
+        (lower, upper)
+    }
+
+    #[test]
+    fn size_and_block_size_pass_through() {
+        let (lower, upper) = two_tier();
+        assert_eq!(upper.size(), lower.size());
+        assert_eq!(upper.sector_size(), lower.sector_size());
+    }
+
+    //上层换出的脏页先进入下层缓存, flush自上而下写回到内存盘
+    #[test]
+    fn flush_propagates_top_down() {
+        let (lower, upper) = two_tier();
+        let data: Vec<u8> = (0..16 * 4096).map(|i| (i / 4096 + 1) as u8).collect();
+        upper.write(&data, 0).unwrap();
+        assert!(upper.cache_len() <= 4);
+        assert!(lower.cache_dump().iter().any(|page| page.dirty));
+
+        BlockDevice::flush(&upper).unwrap();
+        assert!(!upper.cache_dump().iter().any(|page| page.dirty));
+        assert!(!lower.cache_dump().iter().any(|page| page.dirty));
+        let mut disk = vec![0u8; data.len()];
+        lower.uncached_handle().read(&mut disk, 0).unwrap();
+        assert_eq!(disk, data);
+    }
+
+    //上层未命中时经过下层缓存读取下层的内容
+    #[test]
+    fn upper_reads_through_lower() {
+        let (lower, upper) = two_tier();
+        lower.write(&[5u8; 3000], 1000).unwrap();
+        let mut buf = [0u8; 3000];
+        upper.read(&mut buf, 1000).unwrap();
+        assert_eq!(buf, [5u8; 3000]);
+    }
+}
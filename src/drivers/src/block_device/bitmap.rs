@@ -0,0 +1,69 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+//位图, 每一位对应一个扇区或页
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bitmap {
+    bits: Vec<u64>,
+    len: usize,
+}
+
+impl Bitmap {
+    //构造函数, 所有位初始为0
+    pub fn new(len: usize) -> Self {
+        Self {
+            bits: vec![0; len.div_ceil(64)],
+            len,
+        }
+    }
+
+    //从保存的原始数据恢复
+    pub fn from_raw(bits: Vec<u64>, len: usize) -> Self {
+        let mut bits = bits;
+        bits.resize(len.div_ceil(64), 0);
+        Self { bits, len }
+    }
+
+    //原始数据, 用于持久化
+    pub fn as_raw(&self) -> &[u64] {
+        &self.bits
+    }
+
+    //位数
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    //查询, 越界视为0
+    pub fn get(&self, idx: usize) -> bool {
+        idx < self.len && self.bits[idx / 64] & (1 << (idx % 64)) != 0
+    }
+
+    //置位, 越界忽略
+    pub fn set(&mut self, idx: usize) {
+        if idx < self.len {
+            self.bits[idx / 64] |= 1 << (idx % 64);
+        }
+    }
+
+    //清除, 越界忽略
+    pub fn clear(&mut self, idx: usize) {
+        if idx < self.len {
+            self.bits[idx / 64] &= !(1 << (idx % 64));
+        }
+    }
+
+    //为1的位数
+    pub fn count_ones(&self) -> usize {
+        self.bits.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    //遍历所有为1的位
+    pub fn iter_ones(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.len).filter(move |&idx| self.get(idx))
+    }
+}
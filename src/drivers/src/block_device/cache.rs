@@ -0,0 +1,332 @@
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use core::ops::Range;
+use lru::LruCache;
+
+//页缓存的替换策略, 只记录页号, 页数据由PageCache持有
+pub trait EvictionPolicy: Send {
+    //缓存命中
+    fn touch(&mut self, page_id: usize);
+    //新页加入缓存
+    fn insert(&mut self, page_id: usize);
+    //选出一个被换出的页并不再跟踪它
+    fn evict(&mut self) -> Option<usize>;
+    //页被主动移出缓存
+    fn remove(&mut self, page_id: usize);
+    //当前跟踪的页按换出先后排列, 第一个最先被换出, 不影响替换顺序
+    fn order(&self) -> Vec<usize>;
+}
+
+//最近最少使用
+pub struct LruPolicy {
+    order: LruCache<usize, ()>,
+}
+
+impl LruPolicy {
+    pub fn new() -> Self {
+        Self {
+            order: LruCache::unbounded(),
+        }
+    }
+}
+
+impl Default for LruPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EvictionPolicy for LruPolicy {
+    fn touch(&mut self, page_id: usize) {
+        self.order.promote(&page_id);
+    }
+
+    fn insert(&mut self, page_id: usize) {
+        self.order.put(page_id, ());
+    }
+
+    fn evict(&mut self) -> Option<usize> {
+        self.order.pop_lru().map(|(id, _)| id)
+    }
+
+    fn remove(&mut self, page_id: usize) {
+        self.order.pop(&page_id);
+    }
+
+    fn order(&self) -> Vec<usize> {
+        self.order.iter().rev().map(|(&id, _)| id).collect()
+    }
+}
+
+//CLOCK(二次机会)算法, 顺序扫描只会让每页获得一次机会, 不会冲掉常用页
+pub struct ClockPolicy {
+    ring: VecDeque<usize>,             //队首为时钟指针
+    referenced: BTreeMap<usize, bool>, //访问位
+}
+
+impl ClockPolicy {
+    pub fn new() -> Self {
+        Self {
+            ring: VecDeque::new(),
+            referenced: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for ClockPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EvictionPolicy for ClockPolicy {
+    fn touch(&mut self, page_id: usize) {
+        if let Some(referenced) = self.referenced.get_mut(&page_id) {
+            *referenced = true;
+        }
+    }
+
+    fn insert(&mut self, page_id: usize) {
+        self.ring.push_back(page_id);
+        self.referenced.insert(page_id, false);
+    }
+
+    fn evict(&mut self) -> Option<usize> {
+        loop {
+            let id = self.ring.pop_front()?;
+            let referenced = self.referenced.get_mut(&id).unwrap();
+            if *referenced {
+                //清除访问位, 给一次机会
+                *referenced = false;
+                self.ring.push_back(id);
+            } else {
+                self.referenced.remove(&id);
+                return Some(id);
+            }
+        }
+    }
+
+    fn remove(&mut self, page_id: usize) {
+        if self.referenced.remove(&page_id).is_some() {
+            self.ring.retain(|&id| id != page_id);
+        }
+    }
+
+    //从时钟指针开始, 访问位为0的页先于访问位为1的页
+    fn order(&self) -> Vec<usize> {
+        let (mut order, referenced): (Vec<usize>, Vec<usize>) = self
+            .ring
+            .iter()
+            .copied()
+            .partition(|id| !self.referenced[id]);
+        order.extend(referenced);
+        order
+    }
+}
+
+//容量固定的页缓存, 满时由替换策略选择换出的页, 被固定或借出的页不会被换出
+pub struct PageCache<V> {
+    pages: BTreeMap<usize, V>,
+    policy: Box<dyn EvictionPolicy>,
+    capacity: usize,
+    pinned: BTreeMap<usize, usize>, //页号 -> 固定计数, 与页是否在缓存中无关
+    borrowed: BTreeMap<usize, usize>, //缓存中的页号 -> 借出计数
+    detached: Vec<(usize, V, usize)>, //借出期间被移出缓存的页, (页号, 页, 借出计数)
+}
+
+impl<V> PageCache<V> {
+    pub fn new(capacity: usize, policy: Box<dyn EvictionPolicy>) -> Self {
+        assert!(capacity > 0, "cache capacity must not be zero");
+        Self {
+            pages: BTreeMap::new(),
+            policy,
+            capacity,
+            pinned: BTreeMap::new(),
+            borrowed: BTreeMap::new(),
+            detached: Vec::new(),
+        }
+    }
+
+    //修改容量, 只能在缓存为空时调用
+    pub fn set_capacity(&mut self, capacity: usize) {
+        assert!(capacity > 0, "cache capacity must not be zero");
+        assert!(self.pages.is_empty(), "cache is not empty");
+        self.capacity = capacity;
+    }
+
+    //修改容量, 缩小时按替换策略换出多余的页并返回. 固定或借出的页不会被换出,
+    //这些页过多时缓存中的页数仍可能超过新容量, 之后不再加入新页直到降到容量以下
+    pub fn resize(&mut self, capacity: usize) -> Vec<(usize, V)> {
+        assert!(capacity > 0, "cache capacity must not be zero");
+        self.capacity = capacity;
+        let mut evicted = Vec::new();
+        while self.pages.len() > capacity {
+            match self.evict_unpinned() {
+                Some(page) => evicted.push(page),
+                None => break,
+            }
+        }
+        evicted
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    //缓存中的页数
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    pub fn contains(&self, page_id: &usize) -> bool {
+        self.pages.contains_key(page_id)
+    }
+
+    //查找页并记录一次访问
+    pub fn get(&mut self, page_id: &usize) -> Option<&V> {
+        let page = self.pages.get(page_id)?;
+        self.policy.touch(*page_id);
+        Some(page)
+    }
+
+    pub fn get_mut(&mut self, page_id: &usize) -> Option<&mut V> {
+        let page = self.pages.get_mut(page_id)?;
+        self.policy.touch(*page_id);
+        Some(page)
+    }
+
+    //查找页, 不影响替换顺序
+    pub fn peek(&self, page_id: &usize) -> Option<&V> {
+        self.pages.get(page_id)
+    }
+
+    //加入新页, 缓存已满时返回被换出的页
+    pub fn push(&mut self, page_id: usize, page: V) -> Option<(usize, V)> {
+        if let Some(old) = self.pages.insert(page_id, page) {
+            self.policy.touch(page_id);
+            return self.detach(page_id, old).map(|old| (page_id, old));
+        }
+        let evicted = if self.pages.len() > self.capacity {
+            self.evict_unpinned()
+        } else {
+            None
+        };
+        self.policy.insert(page_id);
+        evicted
+    }
+
+    //按替换策略选出第一个未固定的页, 跳过的固定页重新交给策略跟踪
+    fn evict_unpinned(&mut self) -> Option<(usize, V)> {
+        let mut skipped = Vec::new();
+        let mut victim = None;
+        while let Some(id) = self.policy.evict() {
+            if self.pinned.contains_key(&id) || self.borrowed.contains_key(&id) {
+                skipped.push(id);
+            } else {
+                victim = Some(id);
+                break;
+            }
+        }
+        for id in skipped {
+            self.policy.insert(id);
+        }
+        victim.map(|id| (id, self.pages.remove(&id).unwrap()))
+    }
+
+    //缓存已满且所有页都被固定或借出, 此时无法再加入新页
+    pub fn all_pinned(&self) -> bool {
+        self.pages.len() >= self.capacity
+            && self
+                .pages
+                .keys()
+                .all(|id| self.pinned.contains_key(id) || self.borrowed.contains_key(id))
+    }
+
+    //固定页, 可以嵌套
+    pub fn pin(&mut self, page_id: usize) {
+        *self.pinned.entry(page_id).or_insert(0) += 1;
+    }
+
+    //取消一次固定
+    pub fn unpin(&mut self, page_id: usize) {
+        if let Some(count) = self.pinned.get_mut(&page_id) {
+            *count -= 1;
+            if *count == 0 {
+                self.pinned.remove(&page_id);
+            }
+        }
+    }
+
+    pub fn is_pinned(&self, page_id: &usize) -> bool {
+        self.pinned.contains_key(page_id)
+    }
+
+    //缓存中的页按换出先后排列, 第一个最先被换出, 不影响替换顺序
+    pub fn eviction_order(&self) -> Vec<usize> {
+        self.policy.order()
+    }
+
+    //range内已缓存的页号
+    pub fn cached_in(&self, range: Range<usize>) -> Vec<usize> {
+        self.pages.range(range).map(|(&id, _)| id).collect()
+    }
+
+    //借出页: 归还之前页不会被换出, 即使被pop移出缓存也不会被释放. 页不在缓存中时返回None
+    pub fn borrow(&mut self, page_id: usize) -> Option<&V> {
+        let page = self.pages.get(&page_id)?;
+        self.policy.touch(page_id);
+        *self.borrowed.entry(page_id).or_insert(0) += 1;
+        Some(page)
+    }
+
+    //归还一次借出, is_same用于区分借出的页是否仍在缓存中, 还是已被移出后又加载了新的同号页.
+    //已移出缓存的页在最后一次归还时释放
+    pub fn release(&mut self, page_id: usize, is_same: impl Fn(&V) -> bool) {
+        if self.pages.get(&page_id).is_some_and(&is_same) {
+            if let Some(count) = self.borrowed.get_mut(&page_id) {
+                *count -= 1;
+                if *count == 0 {
+                    self.borrowed.remove(&page_id);
+                }
+            }
+            return;
+        }
+        let pos = self
+            .detached
+            .iter()
+            .position(|(id, page, _)| *id == page_id && is_same(page));
+        if let Some(pos) = pos {
+            self.detached[pos].2 -= 1;
+            if self.detached[pos].2 == 0 {
+                self.detached.swap_remove(pos);
+            }
+        }
+    }
+
+    pub fn is_borrowed(&self, page_id: &usize) -> bool {
+        self.borrowed.contains_key(page_id)
+    }
+
+    //已移出缓存的页仍被借出时转入detached, 返回None; 否则原样返回
+    fn detach(&mut self, page_id: usize, page: V) -> Option<V> {
+        match self.borrowed.remove(&page_id) {
+            Some(count) => {
+                self.detached.push((page_id, page, count));
+                None
+            }
+            None => Some(page),
+        }
+    }
+
+    //移除页, 页仍被借出时在归还后才释放, 此时返回None
+    pub fn pop(&mut self, page_id: &usize) -> Option<V> {
+        let page = self.pages.remove(page_id)?;
+        self.policy.remove(*page_id);
+        self.detach(*page_id, page)
+    }
+}
@@ -0,0 +1,171 @@
+use super::SECTOR_SIZE;
+use alloc::sync::Arc;
+use alloc::vec;
+use config::FRAME_SIZE;
+use constants::{AlienResult, LinuxErrno};
+use core::cmp::min;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use device_interface::{BlockDevice, BlockHealth, DeviceBase, HealthStatus};
+use ksync::Mutex;
+
+const CHECKSUM_PAGE_SIZE: usize = FRAME_SIZE;
+const CHECKSUM_SIZE: usize = 4;
+//每个校验页可以保存的校验和数量
+const CHECKSUMS_PER_PAGE: usize = CHECKSUM_PAGE_SIZE / CHECKSUM_SIZE;
+
+//CRC32C(Castagnoli) 查找表
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x82F6_3B78
+            } else {
+                crc >> 1
+            };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+//计算CRC32C
+pub fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc = CRC32C_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+//带校验的块设备, 每个4KiB页的CRC32C保存在设备末尾的保留区
+//保留区中为0的校验和表示该页从未写入过, 读取时不做校验
+pub struct ChecksummedBlock {
+    inner: Arc<dyn BlockDevice>,
+    data_pages: usize,       //可用数据页数
+    corruption: AtomicUsize, //检测到的损坏次数
+    write_lock: Mutex<()>,   //保证数据与校验和一起更新
+}
+
+impl ChecksummedBlock {
+    //构造函数, 每1024个数据页占用1个校验页
+    pub fn new(inner: Arc<dyn BlockDevice>) -> Self {
+        let total_pages = inner.size() / CHECKSUM_PAGE_SIZE;
+        let checksum_pages = (total_pages + CHECKSUMS_PER_PAGE) / (CHECKSUMS_PER_PAGE + 1);
+        Self {
+            inner,
+            data_pages: total_pages - checksum_pages,
+            corruption: AtomicUsize::new(0),
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    //检测到的损坏次数
+    pub fn corruption_count(&self) -> usize {
+        self.corruption.load(Ordering::Relaxed)
+    }
+
+    //页校验和在底层设备中的偏移
+    fn checksum_offset(&self, page_id: usize) -> usize {
+        self.data_pages * CHECKSUM_PAGE_SIZE + page_id * CHECKSUM_SIZE
+    }
+
+    fn read_checksum(&self, page_id: usize) -> AlienResult<u32> {
+        let mut buf = [0u8; CHECKSUM_SIZE];
+        self.inner.read(&mut buf, self.checksum_offset(page_id))?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn write_checksum(&self, page_id: usize, checksum: u32) -> AlienResult<()> {
+        self.inner
+            .write(&checksum.to_le_bytes(), self.checksum_offset(page_id))?;
+        Ok(())
+    }
+
+    //读取整页并校验
+    fn read_page(&self, page_id: usize, page: &mut [u8]) -> AlienResult<()> {
+        self.inner.read(page, page_id * CHECKSUM_PAGE_SIZE)?;
+        let expected = self.read_checksum(page_id)?;
+        if expected != 0 && expected != crc32c(page) {
+            self.corruption.fetch_add(1, Ordering::Relaxed);
+            return Err(LinuxErrno::EIO);
+        }
+        Ok(())
+    }
+}
+
+impl DeviceBase for ChecksummedBlock {
+    fn hand_irq(&self) {}
+}
+
+impl BlockDevice for ChecksummedBlock {
+    //读取并校验, 校验失败返回EIO
+    fn read(&self, buf: &mut [u8], offset: usize) -> AlienResult<usize> {
+        let len = min(buf.len(), self.size().saturating_sub(offset));
+        let mut page = vec![0u8; CHECKSUM_PAGE_SIZE];
+        let mut count = 0;
+        while count < len {
+            let pos = offset + count;
+            let page_id = pos / CHECKSUM_PAGE_SIZE;
+            let page_offset = pos % CHECKSUM_PAGE_SIZE;
+            let copy_len = min(CHECKSUM_PAGE_SIZE - page_offset, len - count);
+            self.read_page(page_id, &mut page)?;
+            buf[count..count + copy_len]
+                .copy_from_slice(&page[page_offset..page_offset + copy_len]);
+            count += copy_len;
+        }
+        Ok(count)
+    }
+
+    //写入数据并更新校验和, 非整页写入先读出并校验原页
+    fn write(&self, buf: &[u8], offset: usize) -> AlienResult<usize> {
+        let _guard = self.write_lock.lock();
+        let len = min(buf.len(), self.size().saturating_sub(offset));
+        let mut page = vec![0u8; CHECKSUM_PAGE_SIZE];
+        let mut count = 0;
+        while count < len {
+            let pos = offset + count;
+            let page_id = pos / CHECKSUM_PAGE_SIZE;
+            let page_offset = pos % CHECKSUM_PAGE_SIZE;
+            let copy_len = min(CHECKSUM_PAGE_SIZE - page_offset, len - count);
+            if copy_len != CHECKSUM_PAGE_SIZE {
+                self.read_page(page_id, &mut page)?;
+            }
+            page[page_offset..page_offset + copy_len]
+                .copy_from_slice(&buf[count..count + copy_len]);
+            self.inner.write(&page, page_id * CHECKSUM_PAGE_SIZE)?;
+            self.write_checksum(page_id, crc32c(&page))?;
+            count += copy_len;
+        }
+        Ok(count)
+    }
+
+    //去掉末尾校验区后的扇区数
+    fn sector_count(&self) -> usize {
+        self.data_pages * CHECKSUM_PAGE_SIZE / SECTOR_SIZE
+    }
+
+    fn flush(&self) -> AlienResult<()> {
+        self.inner.flush()
+    }
+
+    fn needs_flush(&self) -> bool {
+        self.inner.needs_flush()
+    }
+
+    //校验失败的读取计为读取错误, 并使状态至少为Degraded
+    fn health(&self) -> BlockHealth {
+        let corruption = self.corruption_count();
+        let mut health = self.inner.health();
+        health.read_errors += corruption;
+        if corruption > 0 {
+            health.status = health.status.max(HealthStatus::Degraded);
+        }
+        health
+    }
+}
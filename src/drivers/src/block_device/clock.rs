@@ -0,0 +1,52 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+use timer::read_timer;
+
+//给脏页打时间戳的时钟, 只要求读数单调不减, 单位由实现决定
+pub trait TimeSource: Send + Sync {
+    fn now(&self) -> usize;
+}
+
+//平台时钟, 读数为timer::read_timer的时钟周期数
+pub struct PlatformClock;
+
+impl TimeSource for PlatformClock {
+    fn now(&self) -> usize {
+        read_timer()
+    }
+}
+
+//只在调用advance/set时走动的时钟, 用于测试按脏页时长写回
+pub struct MockClock {
+    now: AtomicUsize,
+}
+
+impl MockClock {
+    pub const fn new(start: usize) -> Self {
+        Self {
+            now: AtomicUsize::new(start),
+        }
+    }
+
+    //前进ticks
+    pub fn advance(&self, ticks: usize) {
+        self.now.fetch_add(ticks, Ordering::Relaxed);
+    }
+
+    //设置当前读数, 不能小于之前的读数
+    pub fn set(&self, now: usize) {
+        let old = self.now.swap(now, Ordering::Relaxed);
+        debug_assert!(now >= old, "clock must not go backwards");
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl TimeSource for MockClock {
+    fn now(&self) -> usize {
+        self.now.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,71 @@
+//写冲突检测(write-conflict特性), 用于排查文件系统的加锁问题.
+//记录每个脏页在窗口内的写入时刻和页内范围, 同一页再次被写入且范围与其中某次重叠时
+//打印警告并计数. 一次write写入的各页互不重叠, 因此只报告不同写入之间的冲突;
+//页写回后再写入不算冲突
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::ops::Range;
+use log::warn;
+
+const TRACKED_PAGES: usize = 4096; //跟踪的页数超过该值时丢弃窗口外的记录
+
+pub(super) struct ConflictTracker {
+    window: usize, //窗口长度, 单位由时钟决定, 为0时不检测
+    recent: BTreeMap<usize, Vec<(usize, Range<usize>)>>, //页 -> 窗口内的(写入时刻, 页内范围)
+    conflicts: usize, //累计冲突次数
+}
+
+impl ConflictTracker {
+    pub fn new() -> Self {
+        Self {
+            window: 0,
+            recent: BTreeMap::new(),
+            conflicts: 0,
+        }
+    }
+
+    pub fn set_window(&mut self, window: usize) {
+        self.window = window;
+        if window == 0 {
+            self.recent.clear();
+        }
+    }
+
+    pub fn conflicts(&self) -> usize {
+        self.conflicts
+    }
+
+    //记录在now时刻对page_id中range的写入, was_dirty表示写入前该页已经是脏页
+    pub fn record(&mut self, page_id: usize, range: Range<usize>, was_dirty: bool, now: usize) {
+        if self.window == 0 {
+            return;
+        }
+        let window = self.window;
+        let writes = self.recent.entry(page_id).or_default();
+        if was_dirty {
+            writes.retain(|(tick, _)| now.saturating_sub(*tick) < window);
+        } else {
+            writes.clear();
+        }
+        if let Some((tick, prev)) = writes
+            .iter()
+            .find(|(_, prev)| prev.start < range.end && range.start < prev.end)
+        {
+            self.conflicts += 1;
+            warn!(
+                "block device: overlapping writes to page {} within {}: {:?} then {:?}",
+                page_id,
+                now.saturating_sub(*tick),
+                prev,
+                range
+            );
+        }
+        writes.push((now, range));
+        if self.recent.len() > TRACKED_PAGES {
+            self.recent.retain(|_, writes| {
+                writes.retain(|(tick, _)| now.saturating_sub(*tick) < window);
+                !writes.is_empty()
+            });
+        }
+    }
+}
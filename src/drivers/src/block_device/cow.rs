@@ -0,0 +1,239 @@
+use super::bitmap::Bitmap;
+use super::SECTOR_SIZE;
+use alloc::sync::Arc;
+use constants::{AlienResult, LinuxErrno};
+use core::cmp::min;
+use device_interface::{BlockDevice, BlockHealth, DeviceBase};
+use ksync::Mutex;
+
+//写时复制块设备: 只读的基础镜像 + 可写的覆盖层
+pub struct CowBlock {
+    base: Arc<dyn BlockDevice>,    //只读基础设备
+    overlay: Arc<dyn BlockDevice>, //可写覆盖设备
+    bitmap: Mutex<Bitmap>,         //扇区分配位图, 为1表示数据在覆盖层
+}
+
+impl CowBlock {
+    //构造函数, 覆盖层初始为空
+    pub fn new(base: Arc<dyn BlockDevice>, overlay: Arc<dyn BlockDevice>) -> Self {
+        let sectors = base.size() / SECTOR_SIZE;
+        Self::with_bitmap(base, overlay, Bitmap::new(sectors))
+    }
+
+    //使用已保存的位图构造, 用于恢复之前的覆盖层
+    pub fn with_bitmap(
+        base: Arc<dyn BlockDevice>,
+        overlay: Arc<dyn BlockDevice>,
+        bitmap: Bitmap,
+    ) -> Self {
+        assert!(
+            overlay.size() >= base.size(),
+            "overlay is smaller than base"
+        );
+        Self {
+            base,
+            overlay,
+            bitmap: Mutex::new(bitmap),
+        }
+    }
+
+    //当前位图的快照, 调用者可以保存它以便之后提交覆盖层
+    pub fn bitmap(&self) -> Bitmap {
+        self.bitmap.lock().clone()
+    }
+}
+
+impl DeviceBase for CowBlock {
+    fn hand_irq(&self) {}
+}
+
+impl BlockDevice for CowBlock {
+    //按位图选择从覆盖层或基础设备读取, 连续来源相同的扇区合并为一次读取.
+    //某次读取返回的长度不足时停止, 返回已读出的长度
+    fn read(&self, buf: &mut [u8], offset: usize) -> AlienResult<usize> {
+        let bitmap = self.bitmap.lock();
+        let len = min(buf.len(), self.size().saturating_sub(offset));
+        let mut count = 0;
+        while count < len {
+            let pos = offset + count;
+            let in_overlay = bitmap.get(pos / SECTOR_SIZE);
+            //找到来源相同的最长连续区间
+            let mut end = (pos / SECTOR_SIZE + 1) * SECTOR_SIZE;
+            while end < offset + len && bitmap.get(end / SECTOR_SIZE) == in_overlay {
+                end += SECTOR_SIZE;
+            }
+            let run = min(end, offset + len) - pos;
+            let device = if in_overlay {
+                &self.overlay
+            } else {
+                &self.base
+            };
+            let read = device.read(&mut buf[count..count + run], pos)?;
+            count += read;
+            if read < run {
+                break;
+            }
+        }
+        Ok(count)
+    }
+
+    //写入覆盖层, 首次写入不完整的扇区时先从基础设备复制, 复制时读不到整个扇区返回EIO
+    fn write(&self, buf: &[u8], offset: usize) -> AlienResult<usize> {
+        let mut bitmap = self.bitmap.lock();
+        let len = min(buf.len(), self.size().saturating_sub(offset));
+        let mut count = 0;
+        while count < len {
+            let pos = offset + count;
+            let sector = pos / SECTOR_SIZE;
+            let sector_offset = pos % SECTOR_SIZE;
+            let copy_len = min(SECTOR_SIZE - sector_offset, len - count);
+            if bitmap.get(sector) || copy_len == SECTOR_SIZE {
+                self.overlay.write(&buf[count..count + copy_len], pos)?;
+            } else {
+                let mut sector_buf = [0u8; SECTOR_SIZE];
+                if self.base.read(&mut sector_buf, sector * SECTOR_SIZE)? != SECTOR_SIZE {
+                    return Err(LinuxErrno::EIO);
+                }
+                sector_buf[sector_offset..sector_offset + copy_len]
+                    .copy_from_slice(&buf[count..count + copy_len]);
+                self.overlay.write(&sector_buf, sector * SECTOR_SIZE)?;
+            }
+            bitmap.set(sector);
+            count += copy_len;
+        }
+        Ok(count)
+    }
+
+    //清零: 基础设备上本来就是零的整扇区直接移出覆盖层, 不占用覆盖层空间, 其余部分在覆盖层写零
+    fn write_zeroes(&self, offset: usize, len: usize) -> AlienResult<()> {
+        let end = min(offset + len, self.size());
+        let zeros = [0u8; SECTOR_SIZE];
+        let mut base_buf = [0u8; SECTOR_SIZE];
+        let mut pos = offset;
+        while pos < end {
+            let n = min(SECTOR_SIZE - pos % SECTOR_SIZE, end - pos);
+            if n == SECTOR_SIZE {
+                self.base.read(&mut base_buf, pos)?;
+                if base_buf == zeros {
+                    self.bitmap.lock().clear(pos / SECTOR_SIZE);
+                    pos += n;
+                    continue;
+                }
+            }
+            self.write(&zeros[..n], pos)?;
+            pos += n;
+        }
+        Ok(())
+    }
+
+    //大小与基础设备相同
+    fn sector_count(&self) -> usize {
+        self.base.size() / SECTOR_SIZE
+    }
+
+    //只需刷新覆盖层
+    fn flush(&self) -> AlienResult<()> {
+        self.overlay.flush()
+    }
+
+    //基础设备只读, 只需看覆盖层
+    fn needs_flush(&self) -> bool {
+        self.overlay.needs_flush()
+    }
+
+    fn health(&self) -> BlockHealth {
+        self.base.health().merge(self.overlay.health())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fault::{FaultInjectBlock, FaultRule};
+    use super::super::{GenericBlockDevice, RamDisk};
+    use super::*;
+    use alloc::boxed::Box;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    const SECTORS: usize = 16;
+
+    fn ram() -> Arc<dyn BlockDevice> {
+        Arc::new(GenericBlockDevice::new(Box::new(RamDisk::new(SECTORS))).unwrap())
+    }
+
+    //写满与位置相关的数据的基础设备
+    fn base() -> (Arc<dyn BlockDevice>, Vec<u8>) {
+        let base = ram();
+        let data: Vec<u8> = (0..SECTORS * SECTOR_SIZE)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        base.write(&data, 0).unwrap();
+        (base, data)
+    }
+
+    fn read_all(dev: &dyn BlockDevice) -> Vec<u8> {
+        let mut buf = vec![0u8; dev.size()];
+        assert_eq!(dev.read(&mut buf, 0).unwrap(), buf.len());
+        buf
+    }
+
+    //写过的扇区从覆盖层读取, 其余扇区从基础设备读取, 基础设备不被修改
+    #[test]
+    fn reads_select_overlay_or_base() {
+        let (base, data) = base();
+        let dev = CowBlock::new(base.clone(), ram());
+        assert_eq!(read_all(&dev), data);
+
+        let mut model = data.clone();
+        dev.write(&[0xaa; 100], SECTOR_SIZE + 300).unwrap();
+        model[SECTOR_SIZE + 300..SECTOR_SIZE + 400].fill(0xaa);
+        dev.write(&[0xbb; SECTOR_SIZE], 4 * SECTOR_SIZE).unwrap();
+        dev.write(&[0xbb; SECTOR_SIZE], 5 * SECTOR_SIZE).unwrap();
+        model[4 * SECTOR_SIZE..6 * SECTOR_SIZE].fill(0xbb);
+
+        assert_eq!(read_all(&dev), model);
+        //跨越基础设备和覆盖层的读取
+        let mut buf = vec![0u8; 5 * SECTOR_SIZE];
+        dev.read(&mut buf, 200).unwrap();
+        assert_eq!(buf, model[200..200 + 5 * SECTOR_SIZE]);
+        assert_eq!(read_all(base.as_ref()), data);
+        let ones: Vec<usize> = dev.bitmap().iter_ones().collect();
+        assert_eq!(ones, [1, 4, 5]);
+    }
+
+    //保存的位图可以恢复覆盖层, 没有位图时覆盖层中的数据不可见
+    #[test]
+    fn bitmap_restores_overlay() {
+        let (base, data) = base();
+        let overlay = ram();
+        let dev = CowBlock::new(base.clone(), overlay.clone());
+        dev.write(&[0xcc; 10], 3 * SECTOR_SIZE + 5).unwrap();
+        let expected = read_all(&dev);
+        let saved = dev.bitmap();
+        drop(dev);
+
+        let raw = Bitmap::from_raw(saved.as_raw().to_vec(), saved.len());
+        let restored = CowBlock::with_bitmap(base.clone(), overlay.clone(), raw);
+        assert_eq!(read_all(&restored), expected);
+        let fresh = CowBlock::new(base, overlay);
+        assert_eq!(read_all(&fresh), data);
+    }
+
+    //基础设备短读时返回实际读出的长度, 首次写入不完整的扇区时返回EIO
+    #[test]
+    fn short_base_read_is_reported() {
+        let (base, data) = base();
+        let short = Arc::new(FaultInjectBlock::new(base, 1));
+        short.add_rule(FaultRule::ShortRead(SECTOR_SIZE + 10));
+        let dev = CowBlock::new(short, ram());
+        let mut buf = vec![0u8; 4 * SECTOR_SIZE];
+        assert_eq!(dev.read(&mut buf, 0).unwrap(), SECTOR_SIZE + 10);
+        assert_eq!(buf[..SECTOR_SIZE + 10], data[..SECTOR_SIZE + 10]);
+
+        let short = Arc::new(FaultInjectBlock::new(ram(), 1));
+        short.add_rule(FaultRule::ShortRead(10));
+        let dev = CowBlock::new(short, ram());
+        assert_eq!(dev.write(&[1; 4], 100), Err(LinuxErrno::EIO));
+        assert_eq!(dev.bitmap().count_ones(), 0);
+    }
+}
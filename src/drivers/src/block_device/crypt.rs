@@ -0,0 +1,214 @@
+use super::SECTOR_SIZE;
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::KeyInit;
+use aes::Aes128;
+use alloc::sync::Arc;
+use constants::AlienResult;
+use core::cmp::min;
+use device_interface::{BlockDevice, BlockHealth, DeviceBase};
+use ksync::Mutex;
+use xts_mode::{get_tweak_default, Xts128};
+
+//AES-128-XTS 密钥, 前16字节为数据密钥, 后16字节为tweak密钥
+pub struct XtsKey {
+    cipher: Xts128<Aes128>,
+}
+
+impl XtsKey {
+    //构造函数
+    pub fn new(key: &[u8; 32]) -> Self {
+        let data_key = Aes128::new(GenericArray::from_slice(&key[..16]));
+        let tweak_key = Aes128::new(GenericArray::from_slice(&key[16..]));
+        Self {
+            cipher: Xts128::new(data_key, tweak_key),
+        }
+    }
+
+    //加密一个扇区, 扇区号作为tweak
+    pub fn encrypt_sector(&self, sector: usize, buf: &mut [u8]) {
+        self.cipher
+            .encrypt_sector(buf, get_tweak_default(sector as u128));
+    }
+
+    //解密一个扇区
+    pub fn decrypt_sector(&self, sector: usize, buf: &mut [u8]) {
+        self.cipher
+            .decrypt_sector(buf, get_tweak_default(sector as u128));
+    }
+}
+
+//透明加密块设备, 按扇区使用AES-XTS加解密
+pub struct EncryptedBlock {
+    inner: Arc<dyn BlockDevice>,
+    key: XtsKey,
+    write_lock: Mutex<()>, //保证非对齐写的读-改-写不被打断
+}
+
+impl EncryptedBlock {
+    //构造函数
+    pub fn new(inner: Arc<dyn BlockDevice>, key: XtsKey) -> Self {
+        Self {
+            inner,
+            key,
+            write_lock: Mutex::new(()),
+        }
+    }
+}
+
+impl DeviceBase for EncryptedBlock {
+    fn hand_irq(&self) {}
+}
+
+impl BlockDevice for EncryptedBlock {
+    //按扇区读取并解密, 非对齐部分经由临时扇区缓冲
+    fn read(&self, buf: &mut [u8], offset: usize) -> AlienResult<usize> {
+        let len = min(buf.len(), self.size().saturating_sub(offset));
+        let mut count = 0;
+        while count < len {
+            let pos = offset + count;
+            let sector = pos / SECTOR_SIZE;
+            let sector_offset = pos % SECTOR_SIZE;
+            let copy_len = min(SECTOR_SIZE - sector_offset, len - count);
+            if copy_len == SECTOR_SIZE {
+                let target = &mut buf[count..count + SECTOR_SIZE];
+                self.inner.read(target, pos)?;
+                self.key.decrypt_sector(sector, target);
+            } else {
+                let mut sector_buf = [0u8; SECTOR_SIZE];
+                self.inner.read(&mut sector_buf, sector * SECTOR_SIZE)?;
+                self.key.decrypt_sector(sector, &mut sector_buf);
+                buf[count..count + copy_len]
+                    .copy_from_slice(&sector_buf[sector_offset..sector_offset + copy_len]);
+            }
+            count += copy_len;
+        }
+        Ok(count)
+    }
+
+    //按扇区加密后写入, 非对齐部分先读出解密再修改
+    fn write(&self, buf: &[u8], offset: usize) -> AlienResult<usize> {
+        let _guard = self.write_lock.lock();
+        let len = min(buf.len(), self.size().saturating_sub(offset));
+        let mut count = 0;
+        while count < len {
+            let pos = offset + count;
+            let sector = pos / SECTOR_SIZE;
+            let sector_offset = pos % SECTOR_SIZE;
+            let copy_len = min(SECTOR_SIZE - sector_offset, len - count);
+            let mut sector_buf = [0u8; SECTOR_SIZE];
+            if copy_len != SECTOR_SIZE {
+                self.inner.read(&mut sector_buf, sector * SECTOR_SIZE)?;
+                self.key.decrypt_sector(sector, &mut sector_buf);
+            }
+            sector_buf[sector_offset..sector_offset + copy_len]
+                .copy_from_slice(&buf[count..count + copy_len]);
+            self.key.encrypt_sector(sector, &mut sector_buf);
+            self.inner.write(&sector_buf, sector * SECTOR_SIZE)?;
+            count += copy_len;
+        }
+        Ok(count)
+    }
+
+    fn sector_count(&self) -> usize {
+        self.inner.size() / SECTOR_SIZE
+    }
+
+    fn flush(&self) -> AlienResult<()> {
+        self.inner.flush()
+    }
+
+    fn needs_flush(&self) -> bool {
+        self.inner.needs_flush()
+    }
+
+    fn health(&self) -> BlockHealth {
+        self.inner.health()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{GenericBlockDevice, RamDisk};
+    use super::*;
+    use alloc::boxed::Box;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn key(hex: &str) -> XtsKey {
+        XtsKey::new(from_hex(hex).as_slice().try_into().unwrap())
+    }
+
+    //IEEE 1619-2007 附录B的向量4, 数据单元为512字节 00..ff 00..ff
+    const VECTOR4_KEY: &str = "2718281828459045235360287471352631415926535897932384626433832795";
+
+    fn vector4_plaintext() -> Vec<u8> {
+        (0..SECTOR_SIZE).map(|i| i as u8).collect()
+    }
+
+    //IEEE 1619-2007 附录B的向量2
+    #[test]
+    fn xts_vector2() {
+        let key = key("1111111111111111111111111111111122222222222222222222222222222222");
+        let mut buf = [0x44u8; 32];
+        key.encrypt_sector(0x33_3333_3333, &mut buf);
+        assert_eq!(
+            buf.as_slice(),
+            from_hex("c454185e6a16936e39334038acef838bfb186fff7480adc4289382ecd6d394f0")
+        );
+        key.decrypt_sector(0x33_3333_3333, &mut buf);
+        assert_eq!(buf, [0x44u8; 32]);
+    }
+
+    #[test]
+    fn xts_vector4() {
+        let key = key(VECTOR4_KEY);
+        let mut buf = vector4_plaintext();
+        key.encrypt_sector(0, &mut buf);
+        assert_eq!(
+            buf[..32],
+            from_hex("27a7479befa1d476489f308cd4cfa6e2a96e4bbe3208ff25287dd3819616e89c")
+        );
+        assert_eq!(buf[496..], from_hex("0a282df920147beabe421ee5319d0568"));
+        key.decrypt_sector(0, &mut buf);
+        assert_eq!(buf, vector4_plaintext());
+    }
+
+    //写入第1个扇区后底层设备上是以扇区号1为tweak的密文
+    #[test]
+    fn sector_number_is_the_tweak() {
+        let inner: Arc<dyn BlockDevice> =
+            Arc::new(GenericBlockDevice::new(Box::new(RamDisk::new(8))).unwrap());
+        let dev = EncryptedBlock::new(inner.clone(), key(VECTOR4_KEY));
+        dev.write(&vector4_plaintext(), SECTOR_SIZE).unwrap();
+        let mut raw = vec![0u8; SECTOR_SIZE];
+        inner.read(&mut raw, SECTOR_SIZE).unwrap();
+        assert_eq!(
+            raw[..32],
+            from_hex("bbf9d6a74a7465fee20f42adf9a623fc954f3b55587e8e429eec6f71e738a390")
+        );
+        assert_eq!(raw[496..], from_hex("9b1c84a52a6a5dc065db2496fc4e84cb"));
+    }
+
+    //非对齐的读写跨越扇区边界, 不影响同一扇区中的其它字节
+    #[test]
+    fn unaligned_roundtrip() {
+        let inner: Arc<dyn BlockDevice> =
+            Arc::new(GenericBlockDevice::new(Box::new(RamDisk::new(8))).unwrap());
+        let dev = EncryptedBlock::new(inner, key(VECTOR4_KEY));
+        let data: Vec<u8> = (0..3 * SECTOR_SIZE).map(|i| (i * 7) as u8).collect();
+        dev.write(&data, 0).unwrap();
+        dev.write(&[0xee; 600], 300).unwrap();
+        let mut expected = data.clone();
+        expected[300..900].fill(0xee);
+        let mut buf = vec![0u8; 3 * SECTOR_SIZE];
+        dev.read(&mut buf, 0).unwrap();
+        assert_eq!(buf, expected);
+    }
+}
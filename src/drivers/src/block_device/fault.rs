@@ -0,0 +1,147 @@
+use super::SECTOR_SIZE;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use constants::{AlienResult, LinuxErrno};
+use core::cmp::min;
+use device_interface::{BlockDevice, BlockHealth, DeviceBase};
+use ksync::Mutex;
+
+//故障注入规则
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultRule {
+    FailNth(usize),    //第n次操作失败(从1开始计数, 读写和刷新都计数)
+    FailSector(usize), //覆盖该扇区的读取失败
+    Random(u32),       //每次操作以permille/1000的概率失败
+    ShortRead(usize),  //读取最多返回给定的字节数
+}
+
+struct FaultState {
+    rules: Vec<FaultRule>,
+    ops: usize, //已执行的操作数
+    rng: u64,   //xorshift64状态
+}
+
+//xorshift64伪随机数
+pub(super) fn next_random(state: &mut u64) -> u64 {
+    let mut x = *state;
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    *state = x;
+    x
+}
+
+impl FaultState {
+    //记录一次操作并判断是否需要失败, sectors为读取覆盖的扇区范围
+    fn should_fail(&mut self, sectors: Option<(usize, usize)>) -> bool {
+        self.ops += 1;
+        let mut fail = false;
+        for rule in self.rules.iter() {
+            fail |= match *rule {
+                FaultRule::FailNth(n) => self.ops == n,
+                FaultRule::FailSector(sector) => {
+                    sectors.is_some_and(|(start, end)| sector >= start && sector < end)
+                }
+                FaultRule::Random(permille) => next_random(&mut self.rng) % 1000 < permille as u64,
+                FaultRule::ShortRead(_) => false,
+            };
+        }
+        fail
+    }
+
+    fn short_read(&self) -> Option<usize> {
+        self.rules
+            .iter()
+            .filter_map(|rule| match rule {
+                FaultRule::ShortRead(n) => Some(*n),
+                _ => None,
+            })
+            .min()
+    }
+}
+
+//故障注入块设备, 按规则返回EIO或短读, 否则转发给内部设备. 用于测试错误处理路径
+pub struct FaultInjectBlock {
+    inner: Arc<dyn BlockDevice>,
+    state: Mutex<FaultState>,
+}
+
+impl FaultInjectBlock {
+    //构造函数, 相同的seed产生相同的随机故障序列
+    pub fn new(inner: Arc<dyn BlockDevice>, seed: u64) -> Self {
+        Self {
+            inner,
+            state: Mutex::new(FaultState {
+                rules: Vec::new(),
+                ops: 0,
+                rng: seed.max(1), //xorshift的状态不能为0
+            }),
+        }
+    }
+
+    pub fn add_rule(&self, rule: FaultRule) {
+        self.state.lock().rules.push(rule);
+    }
+
+    //清除所有规则并重新开始计数
+    pub fn clear_rules(&self) {
+        let mut state = self.state.lock();
+        state.rules.clear();
+        state.ops = 0;
+    }
+
+    //已执行的操作数
+    pub fn op_count(&self) -> usize {
+        self.state.lock().ops
+    }
+}
+
+impl DeviceBase for FaultInjectBlock {
+    fn hand_irq(&self) {
+        self.inner.hand_irq()
+    }
+}
+
+impl BlockDevice for FaultInjectBlock {
+    fn read(&self, buf: &mut [u8], offset: usize) -> AlienResult<usize> {
+        let len = match buf.len() {
+            0 => 0,
+            len => {
+                let sectors = (offset / SECTOR_SIZE, (offset + len - 1) / SECTOR_SIZE + 1);
+                let mut state = self.state.lock();
+                if state.should_fail(Some(sectors)) {
+                    return Err(LinuxErrno::EIO);
+                }
+                state.short_read().map_or(len, |n| min(n, len))
+            }
+        };
+        self.inner.read(&mut buf[..len], offset)
+    }
+
+    fn write(&self, buf: &[u8], offset: usize) -> AlienResult<usize> {
+        if self.state.lock().should_fail(None) {
+            return Err(LinuxErrno::EIO);
+        }
+        self.inner.write(buf, offset)
+    }
+
+    fn sector_count(&self) -> usize {
+        self.inner.sector_count()
+    }
+
+    fn flush(&self) -> AlienResult<()> {
+        if self.state.lock().should_fail(None) {
+            return Err(LinuxErrno::EIO);
+        }
+        self.inner.flush()
+    }
+
+    fn needs_flush(&self) -> bool {
+        self.inner.needs_flush()
+    }
+
+    //只报告底层设备的状态, 注入的错误不计入
+    fn health(&self) -> BlockHealth {
+        self.inner.health()
+    }
+}
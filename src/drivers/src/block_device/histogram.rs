@@ -0,0 +1,120 @@
+use super::Mutex;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use constants::AlienResult;
+use core::cmp::min;
+use device_interface::{BlockDeviceInfo, BlockHealth, LowBlockDevice};
+use timer::get_time_us;
+
+//直方图桶数, 第i个桶统计耗时在[2^i, 2^(i+1))us内的请求, 第0个桶包含不足2us的请求,
+//最后一个桶包含所有更长的请求
+pub const HISTOGRAM_BUCKETS: usize = 24;
+
+//I/O延迟直方图
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IoHistogram {
+    pub read: [u64; HISTOGRAM_BUCKETS],  //read_block耗时分布
+    pub write: [u64; HISTOGRAM_BUCKETS], //write_block耗时分布
+}
+
+impl IoHistogram {
+    //耗时对应的桶
+    fn bucket(us: usize) -> usize {
+        let bits = (usize::BITS - us.leading_zeros()) as usize;
+        min(bits.saturating_sub(1), HISTOGRAM_BUCKETS - 1)
+    }
+}
+
+//统计底层设备每次读写耗时的包装
+pub(super) struct TimedBlockDevice {
+    inner: Box<dyn LowBlockDevice>,
+    histogram: Arc<Mutex<IoHistogram>>,
+}
+
+impl TimedBlockDevice {
+    pub fn new(inner: Box<dyn LowBlockDevice>, histogram: Arc<Mutex<IoHistogram>>) -> Self {
+        Self { inner, histogram }
+    }
+}
+
+impl LowBlockDevice for TimedBlockDevice {
+    fn read_block(&mut self, block_id: usize, buf: &mut [u8]) -> AlienResult<()> {
+        let start = get_time_us();
+        let res = self.inner.read_block(block_id, buf);
+        let bucket = IoHistogram::bucket(get_time_us() - start);
+        self.histogram.lock().read[bucket] += 1;
+        res
+    }
+
+    fn write_block(&mut self, block_id: usize, buf: &[u8]) -> AlienResult<()> {
+        let start = get_time_us();
+        let res = self.inner.write_block(block_id, buf);
+        let bucket = IoHistogram::bucket(get_time_us() - start);
+        self.histogram.lock().write[bucket] += 1;
+        res
+    }
+
+    fn read_blocks(&mut self, block_id: usize, buf: &mut [u8]) -> AlienResult<()> {
+        let start = get_time_us();
+        let res = self.inner.read_blocks(block_id, buf);
+        let bucket = IoHistogram::bucket(get_time_us() - start);
+        self.histogram.lock().read[bucket] += 1;
+        res
+    }
+
+    fn write_blocks(&mut self, block_id: usize, buf: &[u8]) -> AlienResult<()> {
+        let start = get_time_us();
+        let res = self.inner.write_blocks(block_id, buf);
+        let bucket = IoHistogram::bucket(get_time_us() - start);
+        self.histogram.lock().write[bucket] += 1;
+        res
+    }
+
+    fn capacity(&self) -> AlienResult<usize> {
+        self.inner.capacity()
+    }
+
+    fn block_size(&self) -> usize {
+        self.inner.block_size()
+    }
+
+    fn optimal_io_size(&self) -> usize {
+        self.inner.optimal_io_size()
+    }
+
+    fn flush(&mut self) -> AlienResult<()> {
+        self.inner.flush()
+    }
+
+    fn needs_flush(&self) -> bool {
+        self.inner.needs_flush()
+    }
+
+    fn read_only(&self) -> bool {
+        self.inner.read_only()
+    }
+
+    fn health(&self) -> BlockHealth {
+        self.inner.health()
+    }
+
+    fn command(&mut self, req: &[u8], resp: &mut [u8]) -> AlienResult<usize> {
+        self.inner.command(req, resp)
+    }
+
+    fn ack_interrupt(&mut self) {
+        self.inner.ack_interrupt()
+    }
+
+    fn capacity_changed(&mut self) -> bool {
+        self.inner.capacity_changed()
+    }
+
+    fn write_zeroes(&mut self, block_id: usize, count: usize) -> AlienResult<()> {
+        self.inner.write_zeroes(block_id, count)
+    }
+
+    fn info(&self) -> BlockDeviceInfo {
+        self.inner.info()
+    }
+}
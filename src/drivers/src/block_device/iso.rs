@@ -0,0 +1,96 @@
+use alloc::boxed::Box;
+use constants::{AlienResult, LinuxErrno};
+use device_interface::{BlockDeviceInfo, BlockHealth, LowBlockDevice};
+
+//ISO9660等光盘介质的扇区大小
+pub const ISO_SECTOR_SIZE: usize = 2048;
+
+//把底层设备当作只读光盘介质使用: 块大小为2048字节, 每块对应底层连续的若干块,
+//所有写入返回EROFS. 底层块大小必须整除2048, 例如装有ISO镜像的512字节扇区virtio磁盘
+pub struct IsoBlock {
+    inner: Box<dyn LowBlockDevice>,
+    ratio: usize, //每个2048字节块对应的底层块数
+}
+
+impl IsoBlock {
+    pub fn new(inner: Box<dyn LowBlockDevice>) -> Self {
+        let inner_size = inner.block_size();
+        assert!(
+            inner_size > 0 && ISO_SECTOR_SIZE.is_multiple_of(inner_size),
+            "inner block size must divide the ISO sector size"
+        );
+        Self {
+            inner,
+            ratio: ISO_SECTOR_SIZE / inner_size,
+        }
+    }
+}
+
+impl LowBlockDevice for IsoBlock {
+    fn read_block(&mut self, block_id: usize, buf: &mut [u8]) -> AlienResult<()> {
+        if buf.len() != ISO_SECTOR_SIZE {
+            return Err(LinuxErrno::EINVAL);
+        }
+        self.inner.read_blocks(block_id * self.ratio, buf)
+    }
+
+    fn write_block(&mut self, _block_id: usize, _buf: &[u8]) -> AlienResult<()> {
+        Err(LinuxErrno::EROFS)
+    }
+
+    fn read_blocks(&mut self, block_id: usize, buf: &mut [u8]) -> AlienResult<()> {
+        if !buf.len().is_multiple_of(ISO_SECTOR_SIZE) {
+            return Err(LinuxErrno::EINVAL);
+        }
+        self.inner.read_blocks(block_id * self.ratio, buf)
+    }
+
+    fn write_blocks(&mut self, _block_id: usize, _buf: &[u8]) -> AlienResult<()> {
+        Err(LinuxErrno::EROFS)
+    }
+
+    //末尾不足一个2048字节块的部分不可访问
+    fn capacity(&self) -> AlienResult<usize> {
+        Ok(self.inner.capacity()? / self.ratio)
+    }
+
+    fn block_size(&self) -> usize {
+        ISO_SECTOR_SIZE
+    }
+
+    fn optimal_io_size(&self) -> usize {
+        self.inner.optimal_io_size().max(ISO_SECTOR_SIZE)
+    }
+
+    //没有写入, 无需刷新
+    fn needs_flush(&self) -> bool {
+        false
+    }
+
+    fn health(&self) -> BlockHealth {
+        self.inner.health()
+    }
+
+    fn read_only(&self) -> bool {
+        true
+    }
+
+    fn ack_interrupt(&mut self) {
+        self.inner.ack_interrupt()
+    }
+
+    fn write_zeroes(&mut self, _block_id: usize, _count: usize) -> AlienResult<()> {
+        Err(LinuxErrno::EROFS)
+    }
+
+    fn info(&self) -> BlockDeviceInfo {
+        let inner = self.inner.info();
+        BlockDeviceInfo {
+            logical_sector_size: ISO_SECTOR_SIZE,
+            physical_sector_size: inner.physical_sector_size.max(ISO_SECTOR_SIZE),
+            total_sectors: self.capacity().unwrap_or(0),
+            supports_trim: false,
+            supports_flush: false,
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//写日志: 开启后每次写入在生效前先以带校验的记录追加到单独的日志设备.
+//日志设备第0个扇区是超级块, 记录日志中第一条记录的序号; 记录从第1个扇区开始顺序存放,
+//每条记录按扇区对齐, 序号从超级块中的序号开始连续递增. 写回所有脏页后清空日志,
+//即写入新的超级块使已有记录全部失效. 序号在清空后继续递增, 因此旧记录不会被误认为新记录
+use super::checksum::crc32c;
+use super::SECTOR_SIZE;
+use crate::raw::{read_u32_le, read_u64_le};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use constants::{AlienResult, LinuxErrno};
+use device_interface::BlockDevice;
+
+const SUPER_MAGIC: u32 = 0x4a53_5550; //"JSUP"
+const RECORD_MAGIC: u32 = 0x4a52_4543; //"JREC"
+const HEADER_SIZE: usize = 40;
+
+//记录类型
+const KIND_DATA: u32 = 0; //头部之后是写入的数据
+const KIND_ZERO: u32 = 1; //清零, 没有数据
+
+//记录头, 小端序:
+//magic(4) crc(4) seq(8) offset(8) len(8) kind(4) 保留(4), 之后是数据, 补零到扇区边界.
+//crc覆盖crc字段为0时的头部和全部数据
+struct RecordHeader {
+    seq: u64,
+    offset: usize,
+    len: usize,
+    kind: u32,
+}
+
+impl RecordHeader {
+    fn encode(&self, record: &mut [u8]) {
+        record[0..4].copy_from_slice(&RECORD_MAGIC.to_le_bytes());
+        record[4..8].fill(0);
+        record[8..16].copy_from_slice(&self.seq.to_le_bytes());
+        record[16..24].copy_from_slice(&(self.offset as u64).to_le_bytes());
+        record[24..32].copy_from_slice(&(self.len as u64).to_le_bytes());
+        record[32..36].copy_from_slice(&self.kind.to_le_bytes());
+    }
+
+    //解析头部, 魔数不对时返回None
+    fn decode(buf: &[u8]) -> Option<(Self, u32)> {
+        if read_u32_le(buf, 0)? != RECORD_MAGIC {
+            return None;
+        }
+        let header = Self {
+            seq: read_u64_le(buf, 8)?,
+            offset: read_u64_le(buf, 16)? as usize,
+            len: read_u64_le(buf, 24)? as usize,
+            kind: read_u32_le(buf, 32)?,
+        };
+        Some((header, read_u32_le(buf, 4)?))
+    }
+
+    //头部之后的数据长度
+    fn data_len(&self) -> usize {
+        if self.kind == KIND_DATA {
+            self.len
+        } else {
+            0
+        }
+    }
+
+    //记录占用的字节数, 溢出时返回None
+    fn record_size(&self) -> Option<usize> {
+        record_size(self.data_len())
+    }
+}
+
+//数据长度为data_len的记录占用的字节数. data_len可能来自未校验的头部, 因此溢出时返回None
+fn record_size(data_len: usize) -> Option<usize> {
+    HEADER_SIZE
+        .checked_add(data_len)?
+        .div_ceil(SECTOR_SIZE)
+        .checked_mul(SECTOR_SIZE)
+}
+
+//日志中的一次写入
+enum Entry {
+    Data(usize, Vec<u8>), //(偏移, 数据)
+    Zero(usize, usize),   //(偏移, 长度)
+}
+
+//读取超级块中的起始序号, 日志设备未初始化时返回None
+fn read_super(log: &dyn BlockDevice) -> AlienResult<Option<u64>> {
+    let mut buf = [0u8; SECTOR_SIZE];
+    if log.read(&mut buf, 0)? != SECTOR_SIZE {
+        return Err(LinuxErrno::EIO);
+    }
+    if read_u32_le(&buf, 0) != Some(SUPER_MAGIC) || read_u32_le(&buf, 4) != Some(crc_super(&buf)) {
+        return Ok(None);
+    }
+    Ok(read_u64_le(&buf, 8))
+}
+
+//超级块的校验和, 覆盖magic和seq
+fn crc_super(buf: &[u8]) -> u32 {
+    let mut data = [0u8; 12];
+    data[..4].copy_from_slice(&buf[0..4]);
+    data[4..].copy_from_slice(&buf[8..16]);
+    crc32c(&data)
+}
+
+//写入起始序号为seq的超级块并刷新日志设备
+fn write_super(log: &dyn BlockDevice, seq: u64) -> AlienResult<()> {
+    let mut buf = [0u8; SECTOR_SIZE];
+    buf[0..4].copy_from_slice(&SUPER_MAGIC.to_le_bytes());
+    buf[8..16].copy_from_slice(&seq.to_le_bytes());
+    let crc = crc_super(&buf);
+    buf[4..8].copy_from_slice(&crc.to_le_bytes());
+    log.write_barrier(&buf, 0)?;
+    Ok(())
+}
+
+//从超级块开始依次读出有效记录, 遇到魔数, 序号或校验和不符的记录(包括写到一半的记录)时停止.
+//返回有效的记录和下一个可用的序号
+fn scan(log: &dyn BlockDevice) -> AlienResult<(Vec<Entry>, u64)> {
+    let mut seq = match read_super(log)? {
+        Some(seq) => seq,
+        None => return Ok((Vec::new(), 1)),
+    };
+    let mut entries = Vec::new();
+    let mut pos = SECTOR_SIZE;
+    let mut first = [0u8; SECTOR_SIZE];
+    while pos + SECTOR_SIZE <= log.size() {
+        if log.read(&mut first, pos)? != SECTOR_SIZE {
+            break;
+        }
+        let (header, crc) = match RecordHeader::decode(&first) {
+            Some((header, crc)) if header.seq == seq && header.kind <= KIND_ZERO => (header, crc),
+            _ => break,
+        };
+        //长度溢出的头部同样视为日志的末尾
+        let size = match header.record_size() {
+            Some(size) if size <= log.size() - pos => size,
+            _ => break,
+        };
+        let mut record = vec![0u8; size];
+        if log.read(&mut record, pos)? != size {
+            break;
+        }
+        record[4..8].fill(0);
+        let data_len = header.data_len();
+        if crc32c(&record[..HEADER_SIZE + data_len]) != crc {
+            break;
+        }
+        entries.push(match header.kind {
+            KIND_DATA => Entry::Data(
+                header.offset,
+                record[HEADER_SIZE..HEADER_SIZE + data_len].to_vec(),
+            ),
+            _ => Entry::Zero(header.offset, header.len),
+        });
+        seq += 1;
+        pos += size;
+    }
+    Ok((entries, seq))
+}
+
+//重新执行日志中的写入: 按顺序把每条有效记录写入main并刷新, 之后清空日志.
+//崩溃后挂载前调用, 完成崩溃前已进入日志但可能尚未写回的写入. 重复执行是安全的.
+//返回执行的记录数
+pub fn replay_log(log: &dyn BlockDevice, main: &dyn BlockDevice) -> AlienResult<usize> {
+    let (entries, next_seq) = scan(log)?;
+    for entry in entries.iter() {
+        match entry {
+            Entry::Data(offset, data) => {
+                main.write(data, *offset)?;
+            }
+            Entry::Zero(offset, len) => main.write_zeroes(*offset, *len)?,
+        }
+    }
+    main.flush()?;
+    write_super(log, next_seq)?;
+    Ok(entries.len())
+}
+
+//GenericBlockDevice使用的日志写入端
+pub(super) struct Journal {
+    log: Arc<dyn BlockDevice>,
+    head: usize,   //下一条记录的位置
+    next_seq: u64, //下一条记录的序号
+}
+
+impl Journal {
+    //打开日志设备并清空. 日志中已有的记录被丢弃, 需要先用replay_log执行
+    pub fn open(log: Arc<dyn BlockDevice>) -> AlienResult<Self> {
+        if log.size() < 2 * SECTOR_SIZE + HEADER_SIZE {
+            return Err(LinuxErrno::EINVAL);
+        }
+        let (_, next_seq) = scan(log.as_ref())?;
+        let mut journal = Self {
+            log,
+            head: SECTOR_SIZE,
+            next_seq,
+        };
+        journal.reset()?;
+        Ok(journal)
+    }
+
+    //一条记录最多能携带的数据量
+    pub fn max_payload(&self) -> usize {
+        (self.log.size() / SECTOR_SIZE - 1) * SECTOR_SIZE - HEADER_SIZE
+    }
+
+    //剩余空间能否再放下数据长度为data_len的记录
+    pub fn fits(&self, data_len: usize) -> bool {
+        record_size(data_len).is_some_and(|size| size <= self.log.size() - self.head)
+    }
+
+    //追加一条记录并刷新日志设备, data为None时表示把[offset, offset+len)清零.
+    //调用者先用fits检查空间
+    pub fn append(&mut self, offset: usize, len: usize, data: Option<&[u8]>) -> AlienResult<()> {
+        let header = RecordHeader {
+            seq: self.next_seq,
+            offset,
+            len,
+            kind: if data.is_some() { KIND_DATA } else { KIND_ZERO },
+        };
+        let mut record = vec![0u8; header.record_size().ok_or(LinuxErrno::EINVAL)?];
+        header.encode(&mut record);
+        let data_len = header.data_len();
+        if let Some(data) = data {
+            record[HEADER_SIZE..HEADER_SIZE + data_len].copy_from_slice(data);
+        }
+        let crc = crc32c(&record[..HEADER_SIZE + data_len]);
+        record[4..8].copy_from_slice(&crc.to_le_bytes());
+        self.log.write_barrier(&record, self.head)?;
+        self.head += record.len();
+        self.next_seq += 1;
+        Ok(())
+    }
+
+    //清空日志, 调用者保证之前的记录都已写回主设备
+    pub fn reset(&mut self) -> AlienResult<()> {
+        write_super(self.log.as_ref(), self.next_seq)?;
+        self.head = SECTOR_SIZE;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{GenericBlockDevice, RamDisk};
+    use super::*;
+    use alloc::boxed::Box;
+
+    //头部中的长度接近usize::MAX时, scan在校验之前计算记录大小不能溢出, 应视为日志末尾
+    #[test]
+    fn scan_stops_at_overflowing_length() {
+        let log = GenericBlockDevice::new(Box::new(RamDisk::new(16))).unwrap();
+        write_super(&log, 7).unwrap();
+        let mut record = [0u8; SECTOR_SIZE];
+        let header = RecordHeader {
+            seq: 7,
+            offset: 0,
+            len: usize::MAX - HEADER_SIZE / 2,
+            kind: KIND_DATA,
+        };
+        header.encode(&mut record);
+        log.write(&record, SECTOR_SIZE).unwrap();
+        let (entries, next_seq) = scan(&log).unwrap();
+        assert!(entries.is_empty());
+        assert_eq!(next_seq, 7);
+    }
+}
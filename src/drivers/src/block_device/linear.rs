@@ -0,0 +1,161 @@
+use super::SECTOR_SIZE;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use constants::{AlienResult, LinuxErrno};
+use core::cmp::min;
+use device_interface::{BlockDevice, BlockHealth, DeviceBase};
+
+//线性拼接(JBOD)块设备, 将多个设备首尾相连成一个连续设备
+pub struct LinearBlock {
+    devices: Vec<(Arc<dyn BlockDevice>, usize /*起始偏移*/)>,
+    size: usize,
+}
+
+impl LinearBlock {
+    //构造函数, 按给定顺序拼接, 成员大小可以不同
+    pub fn new(members: Vec<Arc<dyn BlockDevice>>) -> Self {
+        let mut devices = Vec::with_capacity(members.len());
+        let mut size = 0;
+        for device in members {
+            let device_size = device.size();
+            devices.push((device, size));
+            size += device_size;
+        }
+        Self { devices, size }
+    }
+
+    //查找包含offset的成员, 返回成员及其起始偏移
+    fn locate(&self, offset: usize) -> Option<&(Arc<dyn BlockDevice>, usize)> {
+        self.devices
+            .iter()
+            .rev()
+            .find(|(device, start)| offset >= *start && offset < *start + device.size())
+    }
+}
+
+impl DeviceBase for LinearBlock {
+    fn hand_irq(&self) {}
+}
+
+impl BlockDevice for LinearBlock {
+    //读取数据, 跨越成员边界的请求被拆分
+    fn read(&self, buf: &mut [u8], offset: usize) -> AlienResult<usize> {
+        let len = min(buf.len(), self.size.saturating_sub(offset));
+        let mut count = 0;
+        while count < len {
+            let pos = offset + count;
+            let (device, start) = self.locate(pos).ok_or(LinuxErrno::EIO)?;
+            let copy_len = min(*start + device.size() - pos, len - count);
+            device.read(&mut buf[count..count + copy_len], pos - start)?;
+            count += copy_len;
+        }
+        Ok(count)
+    }
+
+    //写入数据, 跨越成员边界的请求被拆分
+    fn write(&self, buf: &[u8], offset: usize) -> AlienResult<usize> {
+        let len = min(buf.len(), self.size.saturating_sub(offset));
+        let mut count = 0;
+        while count < len {
+            let pos = offset + count;
+            let (device, start) = self.locate(pos).ok_or(LinuxErrno::EIO)?;
+            let copy_len = min(*start + device.size() - pos, len - count);
+            device.write(&buf[count..count + copy_len], pos - start)?;
+            count += copy_len;
+        }
+        Ok(count)
+    }
+
+    //所有成员扇区数之和
+    fn sector_count(&self) -> usize {
+        self.size / SECTOR_SIZE
+    }
+
+    //刷新所有成员
+    fn flush(&self) -> AlienResult<()> {
+        for (device, _) in self.devices.iter() {
+            device.flush()?;
+        }
+        Ok(())
+    }
+
+    fn needs_flush(&self) -> bool {
+        self.devices.iter().any(|(device, _)| device.needs_flush())
+    }
+
+    fn health(&self) -> BlockHealth {
+        self.devices
+            .iter()
+            .fold(BlockHealth::default(), |health, (device, _)| {
+                health.merge(device.health())
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{GenericBlockDevice, RamDisk};
+    use super::*;
+    use alloc::boxed::Box;
+    use alloc::vec;
+
+    fn ram(sectors: usize) -> Arc<dyn BlockDevice> {
+        Arc::new(GenericBlockDevice::new(Box::new(RamDisk::new(sectors))).unwrap())
+    }
+
+    //两个大小不同的成员, 接缝在第8个扇区末尾
+    fn two_members() -> (Arc<dyn BlockDevice>, Arc<dyn BlockDevice>, LinearBlock) {
+        let (first, second) = (ram(8), ram(24));
+        let linear = LinearBlock::new(vec![first.clone(), second.clone()]);
+        (first, second, linear)
+    }
+
+    #[test]
+    fn size_is_sum_of_members() {
+        let (_, _, linear) = two_members();
+        assert_eq!(linear.size(), 32 * SECTOR_SIZE);
+    }
+
+    //恰好跨越接缝的请求被拆分到两个成员
+    #[test]
+    fn request_spanning_the_seam() {
+        let (first, second, linear) = two_members();
+        let seam = 8 * SECTOR_SIZE;
+        let data: Vec<u8> = (0..200).map(|i| i as u8 + 1).collect();
+        assert_eq!(linear.write(&data, seam - 100).unwrap(), 200);
+
+        let mut tail = [0u8; 100];
+        first.read(&mut tail, seam - 100).unwrap();
+        assert_eq!(tail[..], data[..100]);
+        let mut head = [0u8; 100];
+        second.read(&mut head, 0).unwrap();
+        assert_eq!(head[..], data[100..]);
+
+        let mut buf = [0u8; 200];
+        assert_eq!(linear.read(&mut buf, seam - 100).unwrap(), 200);
+        assert_eq!(buf[..], data[..]);
+    }
+
+    //从接缝开始的请求只落在第二个成员上
+    #[test]
+    fn request_starting_at_the_seam() {
+        let (first, second, linear) = two_members();
+        let seam = 8 * SECTOR_SIZE;
+        linear.write(&[9u8; 10], seam).unwrap();
+        let mut buf = [0u8; 10];
+        second.read(&mut buf, 0).unwrap();
+        assert_eq!(buf, [9u8; 10]);
+        first.read(&mut buf, seam - 10).unwrap();
+        assert_eq!(buf, [0u8; 10]);
+    }
+
+    #[test]
+    fn access_past_the_end_is_truncated() {
+        let (_, _, linear) = two_members();
+        let size = linear.size();
+        assert_eq!(linear.write(&[1u8; 100], size - 40).unwrap(), 40);
+        let mut buf = [0u8; 100];
+        assert_eq!(linear.read(&mut buf, size - 40).unwrap(), 40);
+        assert_eq!(linear.read(&mut buf, size).unwrap(), 0);
+    }
+}
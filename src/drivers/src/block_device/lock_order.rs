@@ -0,0 +1,178 @@
+//GenericBlockDevice内部锁的顺序检查.
+//同一个设备的锁必须按LockRank从小到大获取, 开启lock-order特性后每次加锁前检查当前核
+//已持有的锁, 违反顺序时panic; 关闭时LockOrder是零大小类型, lock直接返回内部锁的守卫.
+//不同设备实例之间不做检查, 因此在分区上叠加缓存(BlockAsLow)不会误报
+use super::Mutex;
+use core::ops::DerefMut;
+#[cfg(test)]
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+//锁的等级, 数值小的先获取
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(not(feature = "lock-order"), allow(dead_code))]
+pub(super) enum LockRank {
+    Journal = 1,
+    Cache,
+    Device,
+    Dirty,
+    Metadata,
+    Victim,
+    Changed,
+    Sparse,
+    Sequential,
+    Written,
+    #[cfg(feature = "write-conflict")]
+    Conflict,
+}
+
+#[cfg(feature = "lock-order")]
+mod check {
+    use super::LockRank;
+    use arch::hart_id;
+    use config::CPU_NUM;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    const SLOTS: usize = 16; //每个核最多同时跟踪的锁数
+
+    //每个核持有的锁, 编码为 实例号 << 8 | 等级, 0表示空
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: AtomicUsize = AtomicUsize::new(0);
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY_SLOTS: [AtomicUsize; SLOTS] = [EMPTY; SLOTS];
+    static HELD: [[AtomicUsize; SLOTS]; CPU_NUM] = [EMPTY_SLOTS; CPU_NUM];
+    static NEXT_INSTANCE: AtomicUsize = AtomicUsize::new(1);
+
+    pub fn new_instance() -> usize {
+        NEXT_INSTANCE.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn acquire(instance: usize, rank: LockRank) {
+        let held = &HELD[hart_id()];
+        for slot in held.iter() {
+            let entry = slot.load(Ordering::Relaxed);
+            if entry != 0 && entry >> 8 == instance && entry & 0xff >= rank as usize {
+                panic!(
+                    "block device lock order violation: {:?} taken while holding rank {}",
+                    rank,
+                    entry & 0xff
+                );
+            }
+        }
+        let entry = instance << 8 | rank as usize;
+        let stored = held.iter().any(|slot| {
+            slot.compare_exchange(0, entry, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        });
+        assert!(stored, "too many block device locks held");
+    }
+
+    pub fn release(instance: usize, rank: LockRank) {
+        let entry = instance << 8 | rank as usize;
+        if let Some(slot) = HELD[hart_id()]
+            .iter()
+            .rev()
+            .find(|slot| slot.load(Ordering::Relaxed) == entry)
+        {
+            slot.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+//一个设备实例的锁顺序上下文
+#[derive(Debug)]
+pub(super) struct LockOrder {
+    #[cfg(feature = "lock-order")]
+    instance: usize,
+}
+
+impl LockOrder {
+    pub fn new() -> Self {
+        Self {
+            #[cfg(feature = "lock-order")]
+            instance: check::new_instance(),
+        }
+    }
+
+    //创建属于该实例的锁
+    #[cfg_attr(not(feature = "lock-order"), allow(unused_variables))]
+    pub fn mutex<T>(&self, rank: LockRank, value: T) -> OrderedMutex<T> {
+        OrderedMutex {
+            inner: Mutex::new(value),
+            #[cfg(test)]
+            acquisitions: AtomicUsize::new(0),
+            #[cfg(feature = "lock-order")]
+            rank,
+            #[cfg(feature = "lock-order")]
+            instance: self.instance,
+        }
+    }
+}
+
+//带等级的锁
+pub struct OrderedMutex<T> {
+    inner: Mutex<T>,
+    #[cfg(test)]
+    acquisitions: AtomicUsize, //加锁次数, 用于测试中检查加锁的频率
+    #[cfg(feature = "lock-order")]
+    rank: LockRank,
+    #[cfg(feature = "lock-order")]
+    instance: usize,
+}
+
+impl<T> OrderedMutex<T> {
+    #[cfg(not(feature = "lock-order"))]
+    pub fn lock(&self) -> impl DerefMut<Target = T> + '_ {
+        #[cfg(test)]
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        self.inner.lock()
+    }
+
+    #[cfg(feature = "lock-order")]
+    pub fn lock(&self) -> impl DerefMut<Target = T> + '_ {
+        #[cfg(test)]
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        check::acquire(self.instance, self.rank);
+        OrderedGuard {
+            guard: self.inner.lock(),
+            rank: self.rank,
+            instance: self.instance,
+        }
+    }
+
+    //创建以来lock被调用的次数
+    #[cfg(test)]
+    pub fn acquisitions(&self) -> usize {
+        self.acquisitions.load(Ordering::Relaxed)
+    }
+}
+
+//释放时从当前核的持有列表中移除
+#[cfg(feature = "lock-order")]
+struct OrderedGuard<G> {
+    guard: G,
+    rank: LockRank,
+    instance: usize,
+}
+
+#[cfg(feature = "lock-order")]
+impl<G: core::ops::Deref> core::ops::Deref for OrderedGuard<G> {
+    type Target = G::Target;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+#[cfg(feature = "lock-order")]
+impl<G: DerefMut> DerefMut for OrderedGuard<G> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+#[cfg(feature = "lock-order")]
+impl<G> Drop for OrderedGuard<G> {
+    fn drop(&mut self) {
+        check::release(self.instance, self.rank);
+    }
+}
@@ -0,0 +1,2581 @@
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use constants::LinuxErrno;
+use core::cmp::{max, min};
+use core::fmt::{Debug, Formatter};
+use core::ops::Range;
+use core::ops::{Deref, DerefMut};
+use core::ptr::{addr_of, NonNull};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::{Context, Poll};
+use virtio_drivers::device::blk::VirtIOBlk;
+use virtio_drivers::transport::mmio::{MmioTransport, VirtIOHeader};
+use virtio_drivers::transport::Transport;
+
+use constants::AlienResult;
+//GenericBlockDevice内部使用的锁, 默认为ksync::Mutex(排队锁),
+//开启spin-lock特性后改用普通自旋锁, 适合单核或启动早期
+#[cfg(not(feature = "spin-lock"))]
+use ksync::Mutex;
+#[cfg(feature = "spin-lock")]
+use ksync::SpinMutex as Mutex;
+
+use crate::hal::HalImpl;
+use crate::raw::{read_u32_le, read_u64_le};
+use bitmap::Bitmap;
+use cache::{EvictionPolicy, LruPolicy, PageCache};
+use clock::{PlatformClock, TimeSource};
+use config::FRAME_SIZE;
+#[cfg(feature = "write-conflict")]
+use conflict::ConflictTracker;
+use device_interface::{
+    Advice, AsyncBlockDevice, BlockDevice, BlockDeviceInfo, BlockHealth, DeviceBase, HealthStatus,
+    LowBlockDevice, BLK_CMD_DISCARD, BLK_CMD_FLUSH, BLK_CMD_GET_ID, BLK_CMD_HEADER_SIZE,
+};
+#[cfg(feature = "latency")]
+use histogram::IoHistogram;
+use journal::Journal;
+use lock_order::{LockOrder, LockRank, OrderedMutex};
+use mem::{alloc_frames, free_frames};
+use platform::config::BLOCK_CACHE_FRAMES;
+use shim::KTask;
+use uncached::UncachedBlock;
+
+pub mod adapter;
+pub mod bitmap;
+pub mod cache;
+pub mod checksum;
+pub mod clock;
+#[cfg(feature = "write-conflict")]
+mod conflict;
+pub mod cow;
+#[cfg(feature = "crypt")]
+pub mod crypt;
+pub mod fault;
+#[cfg(feature = "latency")]
+pub mod histogram;
+pub mod iso;
+pub mod journal;
+pub mod linear;
+mod lock_order;
+pub mod raid;
+#[cfg(any(test, feature = "cache-selfcheck"))]
+pub mod selfcheck;
+pub mod trace;
+pub mod uncached;
+
+const PAGE_CACHE_SIZE: usize = FRAME_SIZE; //默认缓存页大小
+const READAHEAD_PAGES: usize = 8; //顺序区域每次预读的最大页数
+const SECTOR_SIZE: usize = 512;
+const FAULT_THRESHOLD: usize = 8; //连续出错多少次后进入故障状态
+const RETRY_BACKOFF_SPINS: usize = 1024; //第一次重试前的等待, 之后每次加倍
+
+//通用块设备
+pub struct GenericBlockDevice {
+    //锁的获取顺序: journal -> cache -> device -> dirty -> metadata -> 其余
+    pub device: OrderedMutex<Box<dyn LowBlockDevice>>, //底层块设备
+    cache: OrderedMutex<PageCache<FrameTracker>>,      //缓存
+    dirty: OrderedMutex<BTreeMap<usize, usize>>,       //脏页 -> 第一次变脏的时刻
+    metadata: OrderedMutex<BTreeSet<usize>>,           //Ordered模式下标记为元数据的脏页
+    cache_mode: CacheMode,                             //写回方式
+    clock: Arc<dyn TimeSource>,                        //脏页时间戳使用的时钟
+    changed: OrderedMutex<BTreeSet<usize>>,            //上次检查点以来被写过的页
+    sparse: Option<OrderedMutex<Bitmap>>,              //页分配位图, 为0的页视为全零
+    written: Option<OrderedMutex<Bitmap>>,             //开启track_written后累计记录写过的页
+    journal: Option<OrderedMutex<Journal>>,            //写日志, 开启后每次写入先追加到日志设备
+    page_size: usize,                                  //缓存页大小, FRAME_SIZE的整数倍
+    cache_frames: usize,                               //缓存占用的总帧数
+    sector_size: usize,                                //底层设备的块大小
+    max_transfer: usize,                               //单次底层请求的最大字节数, 块大小的整数倍
+    read_only: bool,                                   //底层设备只读, 所有写入返回EROFS
+    discard_on_evict: bool,                            //换出全零的脏页时清零而不写回
+    sectors: AtomicUsize,                              //设备容量(块数), 构造时读取, 在线扩容时更新
+    resized: AtomicBool,                               //容量变化后尚未被take_resized取走
+    scratch_sector: Option<usize>,                     //自检使用的扇区, 默认为最后一个扇区
+    sequential: OrderedMutex<Vec<Range<usize>>>,       //开启预读的页区间
+    victim: OrderedMutex<VecDeque<(usize, FrameTracker)>>, //最近换出的干净页, 队首最旧
+    lock_order: LockOrder,                             //锁顺序检查, 未开启lock-order时为空
+    victim_capacity: usize,                            //victim池的页数, 为0时不启用
+    victim_hits: AtomicUsize,                          //缓存未命中但在victim池中找到的次数
+    io_errors: AtomicUsize,                            //连续的I/O错误次数
+    faulted: AtomicBool,                               //故障状态, 所有读写直接返回EIO
+    closed: AtomicBool,                                //shutdown之后所有读写返回EIO
+    in_flight: AtomicUsize,                            //进行中的读写数
+    quiesced: AtomicBool,                              //quiesce期间新的读写等待
+    retry_limit: AtomicUsize,                          //底层读写失败后的重试次数
+    dirty_limit: AtomicUsize,                          //脏页数上限, 超过时写入同步回写, 0为不限制
+    retries: AtomicUsize,                              //累计重试次数
+    read_errors: AtomicUsize,                          //重试后仍失败的底层读取次数
+    write_errors: AtomicUsize,                         //重试后仍失败的底层写入和刷新次数
+    #[cfg(feature = "latency")]
+    histogram: Arc<Mutex<IoHistogram>>, //底层读写延迟
+    #[cfg(feature = "write-conflict")]
+    conflicts: OrderedMutex<ConflictTracker>, //重叠写入检测
+}
+
+//块设备统计信息
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlockStats {
+    pub retries: usize,     //底层读写的累计重试次数
+    pub victim_hits: usize, //victim池命中次数
+}
+
+//cache_dump中一个缓存页的状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CachePageInfo {
+    pub page_id: usize,
+    pub dirty: bool,         //尚未写回
+    pub pinned: bool,        //被固定或借出, 不会被换出
+    pub lru_position: usize, //按换出先后的位置, 0为下一个被换出的页
+}
+
+//缓存的写回方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CacheMode {
+    #[default]
+    WriteBack, //写入只修改缓存, 脏页在flush或换出时写回
+    WriteThrough, //每次写入在返回前写回涉及的页, 不刷新底层设备
+    Ordered,      //同WriteBack, 但写回元数据页之前先写回所有数据页并刷新底层设备
+}
+
+//write_tagged写入的内容类型, 只在Ordered模式下影响写回顺序
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteTag {
+    Data,
+    Metadata,
+}
+
+//底层访问的方向, 用于分别统计读写错误
+#[derive(Debug, Clone, Copy)]
+enum IoDir {
+    Read,
+    Write,
+}
+
+//帧追踪器, 管理连续的若干个帧
+#[derive(Debug)]
+struct FrameTracker {
+    ptr: usize,
+    frames: usize, //帧数
+}
+
+//实现 帧追踪器
+impl FrameTracker {
+    pub fn new(ptr: usize, frames: usize) -> Self {
+        Self { ptr, frames }
+    }
+}
+
+//实现 帧追踪器 的 Deref 和 DerefMut
+impl Deref for FrameTracker {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { core::slice::from_raw_parts(self.ptr as *const u8, self.frames * FRAME_SIZE) }
+    }
+}
+
+//实现 帧追踪器 的 DerefMut
+impl DerefMut for FrameTracker {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { core::slice::from_raw_parts_mut(self.ptr as *mut u8, self.frames * FRAME_SIZE) }
+    }
+}
+
+//实现 帧追踪器 的 Drop
+impl Drop for FrameTracker {
+    fn drop(&mut self) {
+        free_frames(self.ptr as *mut u8, self.frames);
+    }
+}
+
+//长时间读写的取消检查, read_cancellable/write_cancellable在每个缓存页之前调用
+pub trait Cancel {
+    fn cancelled(&self) -> bool;
+}
+
+//由其他任务或中断置位的取消标志
+impl Cancel for AtomicBool {
+    fn cancelled(&self) -> bool {
+        self.load(Ordering::Relaxed)
+    }
+}
+
+//任务收到信号(例如被kill)时取消, 可以传入shim::current_task()
+impl Cancel for dyn KTask {
+    fn cancelled(&self) -> bool {
+        self.have_signal()
+    }
+}
+
+//quiesce返回的守卫, 释放后恢复读写
+pub struct QuiesceGuard<'a> {
+    device: &'a GenericBlockDevice,
+}
+
+impl Drop for QuiesceGuard<'_> {
+    fn drop(&mut self) {
+        self.device.quiesced.store(false, Ordering::Release);
+    }
+}
+
+//一次进行中的读写, 结束时减少计数
+struct OpGuard<'a> {
+    in_flight: &'a AtomicUsize,
+}
+
+impl Drop for OpGuard<'_> {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+//read_borrow返回的缓存页借用, 存在期间页不会被换出或释放.
+//借用的是缓存页本身而不是快照, 之后对该页的写入可以通过它看到
+pub struct CacheGuard<'a> {
+    device: &'a GenericBlockDevice,
+    page_id: usize,
+    frame: usize, //页的起始地址, 用于归还时识别页
+    offset: usize,
+    len: usize,
+}
+
+impl Deref for CacheGuard<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { core::slice::from_raw_parts((self.frame + self.offset) as *const u8, self.len) }
+    }
+}
+
+//归还时需要获取缓存锁, 因此不能在持有缓存锁时释放
+impl Drop for CacheGuard<'_> {
+    fn drop(&mut self) {
+        let frame = self.frame;
+        self.device
+            .cache
+            .lock()
+            .release(self.page_id, |page| page.ptr == frame);
+    }
+}
+
+//Send的作用是：告诉编译器这个类型是可以安全的在多个线程之间传递的
+unsafe impl Send for GenericBlockDevice {}
+
+//Sync的作用是：告诉编译器这个类型是可以安全的在多个线程之间共享的
+unsafe impl Sync for GenericBlockDevice {}
+
+impl GenericBlockDevice {
+    //构造函数
+    //设备无法报告容量时返回其错误, 缓存和位图都按容量建立, 不能猜测大小
+    pub fn new(device: Box<dyn LowBlockDevice>) -> AlienResult<Self> {
+        let sectors = device.capacity()?;
+        let sector_size = device.block_size();
+        assert!(
+            sector_size > 0 && PAGE_CACHE_SIZE.is_multiple_of(sector_size),
+            "block size must divide the cache page size"
+        );
+        //多块请求不超过设备偏好的传输大小, 避免超出virtio的seg_max等限制
+        let max_transfer = max(device.optimal_io_size() / sector_size, 1) * sector_size;
+        let read_only = device.read_only();
+        #[cfg(feature = "latency")]
+        let histogram = Arc::new(Mutex::new(IoHistogram::default()));
+        #[cfg(feature = "latency")]
+        let device: Box<dyn LowBlockDevice> =
+            Box::new(histogram::TimedBlockDevice::new(device, histogram.clone()));
+        let lock_order = LockOrder::new();
+        Ok(Self {
+            device: lock_order.mutex(LockRank::Device, device),
+            cache: lock_order.mutex(
+                LockRank::Cache,
+                PageCache::new(BLOCK_CACHE_FRAMES, Box::new(LruPolicy::new())),
+            ),
+            dirty: lock_order.mutex(LockRank::Dirty, BTreeMap::new()),
+            metadata: lock_order.mutex(LockRank::Metadata, BTreeSet::new()),
+            cache_mode: CacheMode::WriteBack,
+            clock: Arc::new(PlatformClock),
+            changed: lock_order.mutex(LockRank::Changed, BTreeSet::new()),
+            sparse: None,
+            written: None,
+            journal: None,
+            page_size: PAGE_CACHE_SIZE,
+            cache_frames: BLOCK_CACHE_FRAMES,
+            sector_size,
+            max_transfer,
+            read_only,
+            discard_on_evict: false,
+            sectors: AtomicUsize::new(sectors),
+            resized: AtomicBool::new(false),
+            scratch_sector: None,
+            sequential: lock_order.mutex(LockRank::Sequential, Vec::new()),
+            victim: lock_order.mutex(LockRank::Victim, VecDeque::new()),
+            victim_capacity: 0,
+            victim_hits: AtomicUsize::new(0),
+            io_errors: AtomicUsize::new(0),
+            faulted: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+            in_flight: AtomicUsize::new(0),
+            quiesced: AtomicBool::new(false),
+            retry_limit: AtomicUsize::new(0),
+            dirty_limit: AtomicUsize::new(0),
+            retries: AtomicUsize::new(0),
+            read_errors: AtomicUsize::new(0),
+            write_errors: AtomicUsize::new(0),
+            #[cfg(feature = "write-conflict")]
+            conflicts: lock_order.mutex(LockRank::Conflict, ConflictTracker::new()),
+            lock_order,
+            #[cfg(feature = "latency")]
+            histogram,
+        })
+    }
+
+    //只覆盖device中[start_sector, start_sector+sector_count)的缓存, 块号都相对于start_sector.
+    //相当于单个分区加缓存, 同一设备的不同窗口可以各自使用独立的缓存
+    pub fn with_window(
+        device: Box<dyn LowBlockDevice>,
+        start_sector: usize,
+        sector_count: usize,
+    ) -> AlienResult<Self> {
+        Self::new(Box::new(adapter::WindowLow::new(
+            device,
+            start_sector,
+            sector_count,
+        )))
+    }
+
+    //底层read_block/write_block的延迟直方图
+    #[cfg(feature = "latency")]
+    pub fn io_histogram(&self) -> IoHistogram {
+        *self.histogram.lock()
+    }
+
+    //暂停读写: 等待进行中的读写完成后返回, 守卫存在期间新的读写等待, 用于快照或调整大小前
+    //取得一致点. flush和sync_range不受影响, 可以在守卫期间调用以写回脏页.
+    //持有守卫的任务不能再读写本设备, 否则会一直等待
+    pub fn quiesce(&self) -> QuiesceGuard<'_> {
+        while self
+            .quiesced
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        while self.in_flight.load(Ordering::SeqCst) != 0 {
+            core::hint::spin_loop();
+        }
+        QuiesceGuard { device: self }
+    }
+
+    //进行中的读写数
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    //开始一次读写, quiesce期间等待其结束. 与quiesce先置标志再检查计数的顺序相反,
+    //两边都是SeqCst, 因此不会同时通过
+    fn begin_op(&self) -> OpGuard<'_> {
+        loop {
+            while self.quiesced.load(Ordering::Acquire) {
+                core::hint::spin_loop();
+            }
+            self.in_flight.fetch_add(1, Ordering::SeqCst);
+            if !self.quiesced.load(Ordering::SeqCst) {
+                return OpGuard {
+                    in_flight: &self.in_flight,
+                };
+            }
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    //开启重叠写入检测: 同一脏页在window内被两次写入且页内范围重叠时打印警告.
+    //window的单位由时钟决定, 为0时关闭. 只检测经过缓存的写入
+    #[cfg(feature = "write-conflict")]
+    pub fn set_conflict_window(&self, window: usize) {
+        self.conflicts.lock().set_window(window);
+    }
+
+    //检测到的重叠写入次数
+    #[cfg(feature = "write-conflict")]
+    pub fn write_conflicts(&self) -> usize {
+        self.conflicts.lock().conflicts()
+    }
+
+    //上次调用以来设备容量是否发生过变化, 文件系统可以据此重新读取size()
+    pub fn take_resized(&self) -> bool {
+        self.resized.swap(false, Ordering::AcqRel)
+    }
+
+    //设备是否因连续I/O错误处于故障状态
+    pub fn is_faulted(&self) -> bool {
+        self.faulted.load(Ordering::Relaxed)
+    }
+
+    //退出故障状态, 例如更换介质后
+    pub fn reset(&self) {
+        self.io_errors.store(0, Ordering::Relaxed);
+        self.faulted.store(false, Ordering::Relaxed);
+    }
+
+    //故障状态或关闭后不再访问设备
+    fn check_faulted(&self) -> AlienResult<()> {
+        if self.is_faulted() || self.closed.load(Ordering::Acquire) {
+            return Err(LinuxErrno::EIO);
+        }
+        Ok(())
+    }
+
+    //只读设备不接受写入, 在数据进入缓存之前拒绝
+    fn check_writable(&self) -> AlienResult<()> {
+        if self.read_only {
+            return Err(LinuxErrno::EROFS);
+        }
+        Ok(())
+    }
+
+    //记录一次底层访问的结果, 连续出错达到阈值后进入故障状态
+    fn record_io<T>(&self, dir: IoDir, res: AlienResult<T>) -> AlienResult<T> {
+        match res {
+            Ok(_) => self.io_errors.store(0, Ordering::Relaxed),
+            Err(_) => {
+                match dir {
+                    IoDir::Read => self.read_errors.fetch_add(1, Ordering::Relaxed),
+                    IoDir::Write => self.write_errors.fetch_add(1, Ordering::Relaxed),
+                };
+                if self.io_errors.fetch_add(1, Ordering::Relaxed) + 1 >= FAULT_THRESHOLD {
+                    self.faulted.store(true, Ordering::Relaxed);
+                }
+            }
+        }
+        res
+    }
+
+    //底层read_block/write_block失败后最多重试retries次, 默认为0即不重试
+    pub fn set_retry_policy(&self, retries: usize) {
+        self.retry_limit.store(retries, Ordering::Relaxed);
+    }
+
+    //脏页数超过pages时, 写入在返回前同步写回最早变脏的页, 直到脏页数降到pages的一半.
+    //限制写入密集时的内存占用和崩溃时丢失的数据量, 默认为0即不限制
+    pub fn set_dirty_limit(&self, pages: usize) {
+        self.dirty_limit.store(pages, Ordering::Relaxed);
+    }
+
+    //脏页数超过上限时回写到下限
+    fn throttle_dirty(&self) -> AlienResult<()> {
+        let limit = self.dirty_limit.load(Ordering::Relaxed);
+        if limit == 0 {
+            return Ok(());
+        }
+        let count = self.dirty.lock().len();
+        if count > limit {
+            self.background_flush(count - limit / 2)?;
+        }
+        Ok(())
+    }
+
+    //统计信息快照
+    pub fn stats(&self) -> BlockStats {
+        BlockStats {
+            retries: self.retries.load(Ordering::Relaxed),
+            victim_hits: self.victim_hits.load(Ordering::Relaxed),
+        }
+    }
+
+    //执行一次底层块读写, 失败时按重试策略退避后重试, 并记录最终结果
+    fn block_io(&self, dir: IoDir, mut op: impl FnMut() -> AlienResult<()>) -> AlienResult<()> {
+        let mut res = op();
+        let limit = self.retry_limit.load(Ordering::Relaxed);
+        let mut attempt = 0;
+        while res.is_err() && attempt < limit {
+            for _ in 0..RETRY_BACKOFF_SPINS << min(attempt, 16) {
+                core::hint::spin_loop();
+            }
+            self.retries.fetch_add(1, Ordering::Relaxed);
+            attempt += 1;
+            res = op();
+        }
+        self.record_io(dir, res)
+    }
+
+    //返回上次检查点以来被写过的页号并开始新的检查点.
+    //与回写用的脏页集合不同: 脏页写回后就不再是脏页, 而这里的页一直保留到下一次调用,
+    //即使已经写回或被换出. 备份程序可以只读取这些页
+    pub fn take_dirty_snapshot(&self) -> Vec<usize> {
+        let changed = core::mem::take(&mut *self.changed.lock());
+        changed.into_iter().collect()
+    }
+
+    //缓存中所有页的状态快照, 按换出先后排列. 在缓存锁下读取, 不改变替换顺序
+    pub fn cache_dump(&self) -> Vec<CachePageInfo> {
+        let lru = self.cache.lock();
+        let dirty = self.dirty.lock();
+        lru.eviction_order()
+            .into_iter()
+            .enumerate()
+            .map(|(lru_position, page_id)| CachePageInfo {
+                page_id,
+                dirty: dirty.contains_key(&page_id),
+                pinned: lru.is_pinned(&page_id) || lru.is_borrowed(&page_id),
+                lru_position,
+            })
+            .collect()
+    }
+
+    //固定[offset, offset+len)涉及的页, 读入缓存并保证不被换出, 用于常驻的文件系统元数据.
+    //缓存装不下时返回ENOMEM, 本次调用固定的页会被取消
+    pub fn pin(&self, offset: usize, len: usize) -> AlienResult<()> {
+        if len == 0 {
+            return Ok(());
+        }
+        let start_page = offset / self.page_size;
+        let end_page = min((offset + len - 1) / self.page_size + 1, self.page_count());
+        let mut cache_lock = self.cache.lock();
+        for id in start_page..end_page {
+            cache_lock.pin(id);
+            if !cache_lock.contains(&id) {
+                if let Err(e) = self.load_page(&mut cache_lock, id) {
+                    for pinned in start_page..=id {
+                        cache_lock.unpin(pinned);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    //取消pin对[offset, offset+len)的固定
+    pub fn unpin(&self, offset: usize, len: usize) {
+        if len == 0 {
+            return;
+        }
+        let start_page = offset / self.page_size;
+        let end_page = min((offset + len - 1) / self.page_size + 1, self.page_count());
+        let mut cache_lock = self.cache.lock();
+        for id in start_page..end_page {
+            cache_lock.unpin(id);
+        }
+    }
+
+    //使用指定的缓存替换策略, 默认为LRU
+    pub fn with_policy(mut self, policy: Box<dyn EvictionPolicy>) -> Self {
+        let cache = PageCache::new(self.frames_capacity(), policy);
+        self.cache = self.lock_order.mutex(LockRank::Cache, cache);
+        self
+    }
+
+    //换出的脏页全为零时不写回数据: 开启稀疏跟踪时只把页标记为空洞, 否则调用底层设备的
+    //write_zeroes, 由设备决定是否释放空间(例如CowBlock把基础镜像中同样为零的扇区移出覆盖层).
+    //用于保持精简镜像和覆盖层的大小, 默认关闭
+    pub fn with_discard_on_evict(mut self, enable: bool) -> Self {
+        self.discard_on_evict = enable;
+        self
+    }
+
+    //写回方式, 默认为WriteBack
+    pub fn with_cache_mode(mut self, mode: CacheMode) -> Self {
+        self.cache_mode = mode;
+        self
+    }
+
+    //开启二级victim缓存: 被换出的页先放入最多pages页的victim池,
+    //之后的缓存未命中先查找victim池, 命中时直接移回主缓存而不读取设备. 默认为0即关闭
+    pub fn with_victim_cache(mut self, pages: usize) -> Self {
+        self.victim_capacity = pages;
+        self
+    }
+
+    //脏页时间戳使用的时钟, 默认为平台时钟
+    pub fn with_clock(mut self, clock: Arc<dyn TimeSource>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    //self_test使用的扇区, 默认为最后一个扇区
+    pub fn with_scratch_sector(mut self, sector: usize) -> Self {
+        self.scratch_sector = Some(sector);
+        self
+    }
+
+    //自检: 向测试扇区写入固定模式, 读回校验后恢复原内容, 不一致时返回EIO.
+    //检查期间持有缓存锁, 并先写回该扇区所在的缓存页, 因此可以在已挂载的设备上运行
+    pub fn self_test(&self) -> AlienResult<()> {
+        self.check_faulted()?;
+        let sectors = self.sector_count();
+        if sectors == 0 {
+            return Err(LinuxErrno::EINVAL);
+        }
+        let sector = self.scratch_sector.unwrap_or(sectors - 1);
+        if sector >= sectors {
+            return Err(LinuxErrno::EINVAL);
+        }
+        let mut cache_lock = self.cache.lock();
+        let mut device = self.device.lock();
+        if device.read_only() {
+            return Err(LinuxErrno::EROFS);
+        }
+        self.invalidate(
+            &mut cache_lock,
+            device.as_mut(),
+            sector * self.sector_size,
+            self.sector_size,
+        )?;
+        let mut saved = vec![0u8; self.sector_size];
+        self.block_io(IoDir::Read, || device.read_block(sector, &mut saved))?;
+        let mut pattern = vec![0u8; self.sector_size];
+        for (i, byte) in pattern.iter_mut().enumerate() {
+            *byte = 0xa5 ^ i as u8;
+        }
+        let mut readback = vec![0u8; self.sector_size];
+        let res = self
+            .block_io(IoDir::Write, || device.write_block(sector, &pattern))
+            .and_then(|_| self.record_io(IoDir::Write, device.flush()))
+            .and_then(|_| self.block_io(IoDir::Read, || device.read_block(sector, &mut readback)));
+        //无论校验结果如何都要恢复原内容
+        self.block_io(IoDir::Write, || device.write_block(sector, &saved))?;
+        self.record_io(IoDir::Write, device.flush())?;
+        res?;
+        if readback != pattern {
+            return Err(LinuxErrno::EIO);
+        }
+        Ok(())
+    }
+
+    //写回脏页后丢弃所有缓存页和victim池, 设备被绕过本缓存修改后调用, 之后的读取重新访问设备
+    pub fn invalidate_all(&self) -> AlienResult<()> {
+        self.check_faulted()?;
+        let mut lru = self.cache.lock();
+        let mut device = self.device.lock();
+        self.invalidate(&mut lru, device.as_mut(), 0, self.size())
+    }
+
+    //丢弃victim池中range内的页, 设备上的数据被绕过缓存修改时调用
+    fn drop_victims(&self, range: Range<usize>) {
+        self.victim.lock().retain(|(id, _)| !range.contains(id));
+    }
+
+    //每个缓存页使用frames个连续帧, 默认为1. 缓存占用的总帧数不变
+    pub fn with_page_frames(mut self, frames: usize) -> Self {
+        assert!(frames > 0, "cache page must have at least one frame");
+        self.page_size = frames * FRAME_SIZE;
+        let capacity = self.frames_capacity();
+        self.cache.lock().set_capacity(capacity);
+        self
+    }
+
+    //缓存占用的总帧数, 默认为BLOCK_CACHE_FRAMES. 多级缓存(底层设备为BlockAsLow包装的
+    //另一个GenericBlockDevice)时各级分别设置, 通常上层小而下层大
+    pub fn with_cache_frames(mut self, frames: usize) -> Self {
+        assert!(frames > 0, "cache must have at least one frame");
+        self.cache_frames = frames;
+        let capacity = self.frames_capacity();
+        self.cache.lock().set_capacity(capacity);
+        self
+    }
+
+    //按缓存帧数计算的页数
+    fn frames_capacity(&self) -> usize {
+        max(self.cache_frames * FRAME_SIZE / self.page_size, 1)
+    }
+
+    //缓存中的页数
+    pub fn cache_len(&self) -> usize {
+        self.cache.lock().len()
+    }
+
+    //缓存当前能容纳的页数
+    pub fn cache_capacity(&self) -> usize {
+        self.cache.lock().capacity()
+    }
+
+    //把缓存容量改为target页, 用于内存紧张时回收缓存占用的帧. 缩小时按替换顺序换出多余的页,
+    //脏页先写回, 写回失败时容量保持不变. 固定或借出的页不会被换出. target为0时返回EINVAL
+    pub fn shrink_cache(&self, target: usize) -> AlienResult<()> {
+        if target == 0 {
+            return Err(LinuxErrno::EINVAL);
+        }
+        let mut lru = self.cache.lock();
+        let mut device = self.device.lock();
+        let mut dirty = self.dirty.lock();
+        //先写回将被换出的脏页, 全部成功后才修改容量, 出错时缓存和脏页记录都保持不变
+        let excess = lru.len().saturating_sub(target);
+        let ids: Vec<usize> = lru
+            .eviction_order()
+            .into_iter()
+            .filter(|id| !lru.is_pinned(id) && !lru.is_borrowed(id))
+            .take(excess)
+            .filter(|id| dirty.contains_key(id))
+            .collect();
+        self.write_data_first(device.as_mut(), &lru, &mut dirty, &ids)?;
+        for id in ids {
+            if dirty.contains_key(&id) {
+                self.write_back_evicted(device.as_mut(), id, lru.peek(&id).unwrap())?;
+                dirty.remove(&id);
+            }
+        }
+        //换出顺序与eviction_order相同, 这里换出的页都已写回
+        for (id, page) in lru.resize(target) {
+            if dirty.remove(&id).is_some() {
+                self.write_back_evicted(device.as_mut(), id, &page)?;
+            }
+        }
+        Ok(())
+    }
+
+    //设备的总页数
+    fn page_count(&self) -> usize {
+        self.size().div_ceil(self.page_size)
+    }
+
+    //page_id处于顺序区域时预读其后的页
+    fn readahead(&self, cache_lock: &mut PageCache<FrameTracker>, page_id: usize) {
+        let region_end = match self.sequential.lock().iter().find(|r| r.contains(&page_id)) {
+            Some(region) => region.end,
+            None => return,
+        };
+        //预读窗口不超过设备偏好的传输大小, 但至少一页
+        let window = (self.optimal_io_size() / self.page_size).clamp(1, READAHEAD_PAGES);
+        let end = min(min(region_end, page_id + 1 + window), self.page_count());
+        let mut device = self.device.lock();
+        for id in page_id + 1..end {
+            //预读只是优化, 失败时直接停止
+            if !cache_lock.contains(&id)
+                && self
+                    .load_page_with(cache_lock, device.as_mut(), id)
+                    .is_err()
+            {
+                break;
+            }
+        }
+    }
+
+    //开启 read_may_be_sparse 模式: allocated 中为0的页从未写入过,
+    //缓存未命中时直接填零而不读取设备. 默认关闭
+    pub fn read_may_be_sparse(mut self, allocated: Bitmap) -> Self {
+        self.sparse = Some(self.lock_order.mutex(LockRank::Sparse, allocated));
+        self
+    }
+
+    //开启写入跟踪: 记录之后所有被写过(包括清零)的页, 与脏页和检查点不同, 记录只增不减.
+    //页号基于当前的缓存页大小, 因此需要在with_page_frames之后调用. 默认关闭
+    pub fn track_written(mut self) -> Self {
+        let pages = self.size().div_ceil(self.page_size);
+        self.written = Some(self.lock_order.mutex(LockRank::Written, Bitmap::new(pages)));
+        self
+    }
+
+    //开启track_written以来写过的页号, 按升序排列. 镜像工具可以只复制这些页, 未开启时返回None
+    pub fn written_pages(&self) -> Option<Vec<usize>> {
+        let written = self.written.as_ref()?.lock();
+        Some(written.iter_ones().collect())
+    }
+
+    //记录range内的页被写过
+    fn note_written(&self, range: Range<usize>) {
+        if let Some(written) = &self.written {
+            let mut written = written.lock();
+            for id in range.clone() {
+                written.set(id);
+            }
+        }
+        self.changed.lock().extend(range);
+    }
+
+    //开启写日志: 之后的每次写入(包括绕过缓存的写入和清零)先追加到log并刷新, 再写入本设备.
+    //flush写回所有脏页后清空日志. 打开时日志中已有的记录被丢弃, 崩溃后需要先调用
+    //journal::replay_log重新执行其中的写入. 日志设备无法访问或过小时返回错误
+    pub fn with_journal(mut self, log: Arc<dyn BlockDevice>) -> AlienResult<Self> {
+        let journal = Journal::open(log)?;
+        self.journal = Some(self.lock_order.mutex(LockRank::Journal, journal));
+        Ok(self)
+    }
+
+    //开启写日志时先把对[offset, offset+len)的写入追加到日志, 再调用apply(已完成的长度, 本段长度)
+    //执行, data为None表示清零. 数据按单条记录的最大长度分段, 每段追加后立即执行, 日志满时先清空.
+    //整个过程持有日志锁, 因此日志中记录的顺序就是写入生效的顺序
+    fn journaled(
+        &self,
+        offset: usize,
+        len: usize,
+        data: Option<&[u8]>,
+        mut apply: impl FnMut(usize, usize) -> AlienResult<usize>,
+    ) -> AlienResult<usize> {
+        let mut journal = match &self.journal {
+            Some(journal) => journal.lock(),
+            None => return apply(0, len),
+        };
+        //清零记录不携带数据, 不需要分段
+        let chunk = if data.is_some() {
+            journal.max_payload()
+        } else {
+            len
+        };
+        let mut count = 0;
+        while count < len {
+            let n = min(chunk, len - count);
+            let record = data.map(|data| &data[count..count + n]);
+            if !journal.fits(record.map_or(0, |record| record.len())) {
+                self.checkpoint(&mut journal)?;
+            }
+            journal.append(offset + count, n, record)?;
+            count += apply(count, n)?;
+        }
+        Ok(count)
+    }
+
+    //写回所有脏页后清空日志, 调用者持有日志锁
+    fn checkpoint(&self, journal: &mut Journal) -> AlienResult<()> {
+        let lru = self.cache.lock();
+        self.flush_locked(&lru)?;
+        journal.reset()
+    }
+
+    //页是否从未写入过, 只在 read_may_be_sparse 模式下可能为真
+    fn is_hole(&self, page_id: usize) -> bool {
+        self.sparse.as_ref().is_some_and(|sparse| {
+            let sparse = sparse.lock();
+            page_id < sparse.len() && !sparse.get(page_id)
+        })
+    }
+
+    //缓存未命中时读入页, 并写回被换出的页. 内存不足时返回ENOMEM
+    fn load_page(
+        &self,
+        cache_lock: &mut PageCache<FrameTracker>,
+        page_id: usize,
+    ) -> AlienResult<()> {
+        let mut device = self.device.lock(); //设备锁
+        self.load_page_with(cache_lock, device.as_mut(), page_id)
+    }
+
+    //同load_page, 调用者已持有设备锁, 用于连续加载多页时只加一次锁
+    fn load_page_with(
+        &self,
+        cache_lock: &mut PageCache<FrameTracker>,
+        device: &mut dyn LowBlockDevice,
+        page_id: usize,
+    ) -> AlienResult<()> {
+        //所有页都被固定, 没有可以换出的页
+        if cache_lock.all_pinned() {
+            return Err(LinuxErrno::ENOMEM);
+        }
+        let victim = {
+            let mut victims = self.victim.lock();
+            let pos = victims.iter().position(|(id, _)| *id == page_id);
+            pos.and_then(|pos| victims.remove(pos))
+        };
+        if let Some((_, cache)) = victim {
+            //victim池命中, 页内容仍然有效
+            self.victim_hits.fetch_add(1, Ordering::Relaxed);
+            return self.insert_page(cache_lock, device, page_id, cache);
+        }
+        let mut cache = self.alloc_page()?;
+        if self.is_hole(page_id) {
+            //从未写入过的页, 无需访问设备
+            cache.fill(0);
+        } else {
+            //一次读取整页
+            let start_block = page_id * self.page_size / self.sector_size;
+            self.split_read(device, start_block, &mut cache)?;
+        }
+        self.insert_page(cache_lock, device, page_id, cache)
+    }
+
+    //冷缓存上的大范围读取: pages中的页全部未命中时, 用一次整段的设备读取(按max_transfer拆分)
+    //读入临时缓冲区, 再逐页放入缓存. 范围内有页已缓存, 在victim池中, 是空洞, 或页数超过缓存
+    //容量时返回false, 由调用者逐页加载
+    fn load_span(
+        &self,
+        cache_lock: &mut PageCache<FrameTracker>,
+        pages: Range<usize>,
+    ) -> AlienResult<bool> {
+        if pages.len() < 2 || pages.len() > cache_lock.capacity() || cache_lock.all_pinned() {
+            return Ok(false);
+        }
+        if pages
+            .clone()
+            .any(|id| cache_lock.contains(&id) || self.is_hole(id))
+        {
+            return Ok(false);
+        }
+        if self.victim.lock().iter().any(|(id, _)| pages.contains(id)) {
+            return Ok(false);
+        }
+        let mut scratch = vec![0u8; pages.len() * self.page_size];
+        let start_block = pages.start * self.page_size / self.sector_size;
+        let mut device = self.device.lock();
+        self.split_read(device.as_mut(), start_block, &mut scratch)?;
+        for (id, data) in pages.zip(scratch.chunks(self.page_size)) {
+            let mut cache = self.alloc_page()?;
+            cache.copy_from_slice(data);
+            self.insert_page(cache_lock, device.as_mut(), id, cache)?;
+        }
+        Ok(true)
+    }
+
+    //分配一个缓存页的帧
+    fn alloc_page(&self) -> AlienResult<FrameTracker> {
+        let frames = self.page_size / FRAME_SIZE;
+        let cache = alloc_frames(frames); //分配帧
+        if cache.is_null() {
+            return Err(LinuxErrno::ENOMEM);
+        }
+        Ok(FrameTracker::new(cache as usize, frames)) //帧追踪器
+    }
+
+    //为即将被整页覆盖的页分配缓存, 不从设备读取旧内容
+    fn overwrite_page(
+        &self,
+        cache_lock: &mut PageCache<FrameTracker>,
+        device: &mut dyn LowBlockDevice,
+        page_id: usize,
+    ) -> AlienResult<()> {
+        if cache_lock.all_pinned() {
+            return Err(LinuxErrno::ENOMEM);
+        }
+        let cache = self.alloc_page()?;
+        self.drop_victims(page_id..page_id + 1);
+        self.insert_page(cache_lock, device, page_id, cache)
+    }
+
+    //页加入缓存, 被换出的页如果是脏页先写回, 之后放入victim池.
+    //写回失败时放弃加入的页, 被换出的页放回缓存并保持为脏页
+    fn insert_page(
+        &self,
+        cache_lock: &mut PageCache<FrameTracker>,
+        device: &mut dyn LowBlockDevice,
+        page_id: usize,
+        cache: FrameTracker,
+    ) -> AlienResult<()> {
+        let (id, old_cache) = match cache_lock.push(page_id, cache) {
+            Some(old) => old,
+            None => return Ok(()),
+        };
+        //只有脏页需要写回
+        let mut dirty = self.dirty.lock();
+        if dirty.contains_key(&id) {
+            let res = self
+                .write_data_first(device, cache_lock, &mut dirty, &[id])
+                .and_then(|_| self.write_back_evicted(device, id, &old_cache));
+            if let Err(err) = res {
+                cache_lock.pop(&page_id);
+                cache_lock.push(id, old_cache);
+                return Err(err);
+            }
+            dirty.remove(&id);
+        }
+        drop(dirty);
+        if self.victim_capacity > 0 {
+            let mut victims = self.victim.lock();
+            if victims.len() >= self.victim_capacity {
+                victims.pop_front();
+            }
+            victims.push_back((id, old_cache));
+        }
+        Ok(())
+    }
+
+    //后台回写, 写回最多max_pages个最早变脏的页, 返回写回的页数.
+    //由内核定时任务周期调用, 限制崩溃时丢失的数据量
+    pub fn background_flush(&self, max_pages: usize) -> AlienResult<usize> {
+        self.check_faulted()?;
+        let lru = self.cache.lock();
+        let mut device = self.device.lock();
+        let mut dirty = self.dirty.lock();
+        let mut oldest: Vec<(usize, usize)> = dirty.iter().map(|(&id, &tick)| (tick, id)).collect();
+        oldest.sort_unstable();
+        oldest.truncate(max_pages);
+        let ids: Vec<usize> = oldest.iter().map(|&(_, id)| id).collect();
+        self.write_data_first(device.as_mut(), &lru, &mut dirty, &ids)?;
+        //写回成功后才移除脏页记录, 失败的页之后的flush还会重试
+        for &id in ids.iter() {
+            if dirty.contains_key(&id) {
+                self.write_back(device.as_mut(), id, lru.peek(&id).unwrap())?;
+                dirty.remove(&id);
+            }
+        }
+        if !oldest.is_empty() {
+            self.record_io(IoDir::Write, device.flush())?;
+        }
+        Ok(oldest.len())
+    }
+
+    //提交屏障: 返回时之前写入的所有页都已写回并刷新到持久存储, 之后的写入只能在此之后写回.
+    //写回期间持有缓存锁, 屏障后的写入要等屏障完成才能进入缓存. 与flush不同, 开启写日志时
+    //不清空日志. 用于日志文件系统在日志块之后写入提交记录
+    pub fn commit_barrier(&self) -> AlienResult<()> {
+        self.check_faulted()?;
+        let lru = self.cache.lock();
+        self.flush_locked(&lru)
+    }
+
+    //同flush, 在所有脏页写回且底层设备刷新完成后以flush的结果调用cb.
+    //cb在释放设备的锁之后执行, 可以在其中继续读写本设备, 例如写入文件系统的提交块
+    pub fn flush_with_callback(&self, cb: impl FnOnce(AlienResult<()>)) {
+        cb(BlockDevice::flush(self));
+    }
+
+    //写回所有变脏时长不少于age的页, 返回写回的页数. 时刻和age的单位由时钟决定
+    pub fn flush_older_than(&self, age: usize) -> AlienResult<usize> {
+        self.check_faulted()?;
+        let now = self.clock.now();
+        let lru = self.cache.lock();
+        let mut device = self.device.lock();
+        let mut dirty = self.dirty.lock();
+        let old: Vec<usize> = dirty
+            .iter()
+            .filter(|(_, &tick)| now.saturating_sub(tick) >= age)
+            .map(|(&id, _)| id)
+            .collect();
+        self.write_data_first(device.as_mut(), &lru, &mut dirty, &old)?;
+        for &id in old.iter() {
+            if dirty.contains_key(&id) {
+                self.write_back(device.as_mut(), id, lru.peek(&id).unwrap())?;
+                dirty.remove(&id);
+            }
+        }
+        if !old.is_empty() {
+            self.record_io(IoDir::Write, device.flush())?;
+        }
+        Ok(old.len())
+    }
+
+    //只写回与[offset, offset+len)重叠的脏页并刷新设备, 其余脏页不受影响, 用于单个文件的fsync
+    pub fn sync_range(&self, offset: usize, len: usize) -> AlienResult<()> {
+        self.check_faulted()?;
+        if len == 0 {
+            return Ok(());
+        }
+        let start_page = offset / self.page_size;
+        let end_page = (offset + len - 1) / self.page_size + 1;
+        let lru = self.cache.lock();
+        let mut device = self.device.lock();
+        let mut dirty = self.dirty.lock();
+        let ids: Vec<usize> = dirty
+            .range(start_page..end_page)
+            .map(|(&id, _)| id)
+            .collect();
+        self.write_data_first(device.as_mut(), &lru, &mut dirty, &ids)?;
+        let ids: Vec<usize> = dirty
+            .range(start_page..end_page)
+            .map(|(&id, _)| id)
+            .collect();
+        self.write_back_coalesced(device.as_mut(), &lru, &ids)?;
+        for id in ids {
+            dirty.remove(&id);
+        }
+        self.record_io(IoDir::Write, device.flush())
+    }
+
+    //写回所有脏页并刷新底层设备, 调用者持有缓存锁
+    fn flush_locked(&self, lru: &PageCache<FrameTracker>) -> AlienResult<()> {
+        let mut device = self.device.lock();
+        let mut dirty = self.dirty.lock();
+        let ids: Vec<usize> = dirty.keys().copied().collect();
+        self.write_data_first(device.as_mut(), lru, &mut dirty, &ids)?;
+        let ids: Vec<usize> = dirty.keys().copied().collect();
+        self.write_back_coalesced(device.as_mut(), lru, &ids)?;
+        dirty.clear();
+        self.record_io(IoDir::Write, device.flush())
+    }
+
+    //Ordered模式下, ids中有元数据页时先写回所有不是元数据的脏页并刷新底层设备,
+    //保证数据先于引用它的元数据到达设备. 写回的页从dirty中移除, 调用者之后再写回ids中剩余的脏页
+    fn write_data_first(
+        &self,
+        device: &mut dyn LowBlockDevice,
+        lru: &PageCache<FrameTracker>,
+        dirty: &mut BTreeMap<usize, usize>,
+        ids: &[usize],
+    ) -> AlienResult<()> {
+        if self.cache_mode != CacheMode::Ordered {
+            return Ok(());
+        }
+        let metadata = self.metadata.lock();
+        if !ids.iter().any(|id| metadata.contains(id)) {
+            return Ok(());
+        }
+        let data: Vec<usize> = dirty
+            .keys()
+            .filter(|id| !metadata.contains(id))
+            .copied()
+            .collect();
+        drop(metadata);
+        if data.is_empty() {
+            return Ok(());
+        }
+        self.write_back_coalesced(device, lru, &data)?;
+        for id in data {
+            dirty.remove(&id);
+        }
+        self.record_io(IoDir::Write, device.flush())
+    }
+
+    //立即写回[offset, offset+len)中的脏页, 不刷新底层设备
+    fn write_back_range(&self, offset: usize, len: usize) -> AlienResult<()> {
+        let lru = self.cache.lock();
+        let mut device = self.device.lock();
+        let mut dirty = self.dirty.lock();
+        let start_page = offset / self.page_size;
+        let end_page = (offset + len - 1) / self.page_size + 1;
+        //页可能已被其它写入者换出并写回
+        let ids: Vec<usize> = dirty
+            .range(start_page..end_page)
+            .map(|(&id, _)| id)
+            .collect();
+        self.write_data_first(device.as_mut(), &lru, &mut dirty, &ids)?;
+        for id in ids {
+            if dirty.contains_key(&id) {
+                self.write_back(device.as_mut(), id, lru.peek(&id).unwrap())?;
+                dirty.remove(&id);
+            }
+        }
+        Ok(())
+    }
+
+    //按页号顺序写回ids中的页, 相邻的页合并成一次写入, 每次不超过max_transfer字节.
+    //页本身不小于max_transfer时无法合并, 逐页写回. ids必须升序排列
+    fn write_back_coalesced(
+        &self,
+        device: &mut dyn LowBlockDevice,
+        lru: &PageCache<FrameTracker>,
+        ids: &[usize],
+    ) -> AlienResult<()> {
+        let batch_pages = max(self.max_transfer / self.page_size, 1); //每次写入的最大页数
+        let mut buf = Vec::new();
+        let mut i = 0;
+        while i < ids.len() {
+            let mut n = 1;
+            while n < batch_pages && i + n < ids.len() && ids[i + n] == ids[i] + n {
+                n += 1;
+            }
+            if n == 1 {
+                self.write_back(device, ids[i], lru.peek(&ids[i]).unwrap())?;
+            } else {
+                //缓存页的帧不连续, 先拷贝到连续的缓冲区
+                buf.clear();
+                for id in ids[i..i + n].iter() {
+                    buf.extend_from_slice(lru.peek(id).unwrap());
+                }
+                self.split_write(device, ids[i] * self.page_size / self.sector_size, &buf)?;
+            }
+            i += n;
+        }
+        Ok(())
+    }
+
+    //关闭设备: 写回所有脏页并刷新, 之后的读写都返回EIO. 返回刷新的错误, 此时设备同样被关闭.
+    //所有请求都是同步完成的, 持有缓存锁时没有进行中的请求. 重复调用直接返回Ok
+    pub fn shutdown(&self) -> AlienResult<()> {
+        let mut journal = self.journal.as_ref().map(|journal| journal.lock());
+        let lru = self.cache.lock();
+        if self.closed.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        let res = if self.is_faulted() {
+            Err(LinuxErrno::EIO)
+        } else {
+            self.flush_locked(&lru)
+                .and_then(|_| match journal.as_mut() {
+                    Some(journal) => journal.reset(),
+                    None => Ok(()),
+                })
+        };
+        self.closed.store(true, Ordering::Release);
+        res
+    }
+
+    //将一页写回设备
+    fn write_back(
+        &self,
+        device: &mut dyn LowBlockDevice,
+        page_id: usize,
+        cache: &FrameTracker,
+    ) -> AlienResult<()> {
+        let start_block = page_id * self.page_size / self.sector_size; //起始块
+        self.split_write(device, start_block, cache)
+    }
+
+    //从start_block开始连续读取, 按max_transfer拆分成多次底层请求, 每次请求单独重试
+    fn split_read(
+        &self,
+        device: &mut dyn LowBlockDevice,
+        start_block: usize,
+        buf: &mut [u8],
+    ) -> AlienResult<()> {
+        let blocks = self.max_transfer / self.sector_size; //每次请求的块数
+        for (i, chunk) in buf.chunks_mut(self.max_transfer).enumerate() {
+            let block_id = start_block + i * blocks;
+            self.block_io(IoDir::Read, || device.read_blocks(block_id, chunk))?;
+        }
+        Ok(())
+    }
+
+    //从start_block开始连续写入, 拆分方式同split_read
+    fn split_write(
+        &self,
+        device: &mut dyn LowBlockDevice,
+        start_block: usize,
+        buf: &[u8],
+    ) -> AlienResult<()> {
+        let blocks = self.max_transfer / self.sector_size;
+        for (i, chunk) in buf.chunks(self.max_transfer).enumerate() {
+            let block_id = start_block + i * blocks;
+            self.block_io(IoDir::Write, || device.write_blocks(block_id, chunk))?;
+        }
+        Ok(())
+    }
+
+    //绕过页缓存直接访问底层设备的句柄
+    pub fn uncached_handle(&self) -> UncachedBlock<'_> {
+        UncachedBlock::new(self)
+    }
+
+    //直接读, offset和buf长度必须按扇区对齐, 不经过也不污染页缓存
+    pub fn read_direct(&self, buf: &mut [u8], offset: usize) -> AlienResult<usize> {
+        if !offset.is_multiple_of(self.sector_size) || !buf.len().is_multiple_of(self.sector_size) {
+            return Err(LinuxErrno::EINVAL);
+        }
+        self.uncached_handle().read(buf, offset)
+    }
+
+    //直接写, 对齐要求同read_direct
+    pub fn write_direct(&self, buf: &[u8], offset: usize) -> AlienResult<usize> {
+        if !offset.is_multiple_of(self.sector_size) || !buf.len().is_multiple_of(self.sector_size) {
+            return Err(LinuxErrno::EINVAL);
+        }
+        self.uncached_handle().write(buf, offset)
+    }
+
+    //交换页槽位数, 每个槽位FRAME_SIZE字节
+    pub fn swap_slots(&self) -> usize {
+        self.size() / FRAME_SIZE
+    }
+
+    //读取第page_idx个交换页, 不经过页缓存
+    pub fn read_page(&self, page_idx: usize, buf: &mut [u8]) -> AlienResult<()> {
+        if buf.len() != FRAME_SIZE || page_idx >= self.swap_slots() {
+            return Err(LinuxErrno::EINVAL);
+        }
+        self.read_direct(buf, page_idx * FRAME_SIZE)?;
+        Ok(())
+    }
+
+    //写入第page_idx个交换页, 不经过页缓存
+    pub fn write_page(&self, page_idx: usize, buf: &[u8]) -> AlienResult<()> {
+        if buf.len() != FRAME_SIZE || page_idx >= self.swap_slots() {
+            return Err(LinuxErrno::EINVAL);
+        }
+        self.write_direct(buf, page_idx * FRAME_SIZE)?;
+        Ok(())
+    }
+
+    //写回被换出的脏页, 开启discard_on_evict且页全为零时改为丢弃
+    fn write_back_evicted(
+        &self,
+        device: &mut dyn LowBlockDevice,
+        page_id: usize,
+        cache: &FrameTracker,
+    ) -> AlienResult<()> {
+        if !self.discard_on_evict || cache.iter().any(|&b| b != 0) {
+            return self.write_back(device, page_id, cache);
+        }
+        if let Some(sparse) = &self.sparse {
+            sparse.lock().clear(page_id);
+            return Ok(());
+        }
+        //最后一页可能越过设备末尾
+        let sectors = self.page_size / self.sector_size;
+        let start = page_id * sectors;
+        let count = min(sectors, self.sector_count().saturating_sub(start));
+        self.record_io(IoDir::Write, device.write_zeroes(start, count))
+    }
+
+    //使[offset, offset+len)重叠的缓存页失效, 脏页先写回
+    fn invalidate(
+        &self,
+        lru: &mut PageCache<FrameTracker>,
+        device: &mut dyn LowBlockDevice,
+        offset: usize,
+        len: usize,
+    ) -> AlienResult<()> {
+        if len == 0 {
+            return Ok(());
+        }
+        let start_page = offset / self.page_size;
+        let end_page = (offset + len - 1) / self.page_size + 1;
+        let mut dirty = self.dirty.lock();
+        for id in lru.cached_in(start_page..end_page) {
+            if dirty.contains_key(&id) {
+                self.write_data_first(device, lru, &mut dirty, &[id])?;
+                self.write_back(device, id, lru.peek(&id).unwrap())?;
+                dirty.remove(&id);
+            }
+            lru.pop(&id);
+        }
+        drop(dirty);
+        self.drop_victims(start_page..end_page);
+        Ok(())
+    }
+
+    //经过缓存读取, 调用者持有缓存锁
+    fn read_cached(
+        &self,
+        cache_lock: &mut PageCache<FrameTracker>,
+        buf: &mut [u8],
+        offset: usize,
+    ) -> AlienResult<usize> {
+        let len = min(buf.len(), self.size().saturating_sub(offset)); //读取长度, 不超过设备末尾
+        if len > 0 {
+            //整个范围都未命中时一次读入, 之后下面的循环全部命中
+            let pages = offset / self.page_size..(offset + len - 1) / self.page_size + 1;
+            let last = pages.end - 1;
+            if self.load_span(cache_lock, pages)? {
+                self.readahead(cache_lock, last);
+            }
+        }
+        let mut page_id = offset / self.page_size; //页号
+        let mut offset = offset % self.page_size; //偏移
+        let mut count = 0; //计数
+
+        //连续未命中的页共用一次设备锁, 遇到命中的页或读完后释放并从最后一个未命中的页预读.
+        //命中的页在拷贝之后才预读, 它可能正是预读换出的页
+        let mut device = None;
+        let mut readahead_from = None;
+        while count < len {
+            //如果缓存中不包含页号
+            if !cache_lock.contains(&page_id) {
+                let device = device.get_or_insert_with(|| self.device.lock());
+                self.load_page_with(cache_lock, device.as_mut(), page_id)?;
+            } else if device.take().is_some() {
+                readahead_from = Some(page_id - 1);
+            }
+            let cache = cache_lock.get(&page_id).unwrap();
+            let copy_len = min(self.page_size - offset, len - count);
+            //只有第一页从页中间开始, 拷贝不会越过页末尾和buf末尾
+            debug_assert!(offset < self.page_size && offset + copy_len <= cache.len());
+            debug_assert!(count + copy_len <= len && len <= buf.len());
+            buf[count..count + copy_len].copy_from_slice(&cache[offset..offset + copy_len]);
+            count += copy_len;
+            offset = 0;
+            page_id += 1;
+            if let Some(id) = readahead_from.take() {
+                self.readahead(cache_lock, id);
+            }
+        }
+        if device.take().is_some() {
+            self.readahead(cache_lock, page_id - 1);
+        }
+        Ok(count)
+    }
+
+    //经过缓存写入, 调用者持有缓存锁
+    fn write_cached(
+        &self,
+        cache_lock: &mut PageCache<FrameTracker>,
+        buf: &[u8],
+        offset: usize,
+        tag: WriteTag,
+    ) -> AlienResult<usize> {
+        let len = min(buf.len(), self.size().saturating_sub(offset)); //写入长度, 不超过设备末尾
+        let mut page_id = offset / self.page_size;
+        let mut offset = offset % self.page_size;
+        let mut count = 0;
+        //连续未命中的页共用一次设备锁
+        let mut device = None;
+        while count < len {
+            if !cache_lock.contains(&page_id) {
+                let device = device.get_or_insert_with(|| self.device.lock());
+                if offset == 0 && len - count >= self.page_size {
+                    //整页写入, 跳过读取
+                    self.overwrite_page(cache_lock, device.as_mut(), page_id)?;
+                } else {
+                    self.load_page_with(cache_lock, device.as_mut(), page_id)?;
+                }
+            } else {
+                device = None;
+            }
+            let cache = cache_lock.get_mut(&page_id).unwrap();
+            let copy_len = min(self.page_size - offset, len - count);
+            //同read, 只有第一页从页中间开始
+            debug_assert!(offset < self.page_size && offset + copy_len <= cache.len());
+            debug_assert!(count + copy_len <= len && len <= buf.len());
+            cache[offset..offset + copy_len].copy_from_slice(&buf[count..count + copy_len]);
+            if let Some(sparse) = &self.sparse {
+                sparse.lock().set(page_id);
+            }
+            {
+                let mut dirty = self.dirty.lock();
+                #[cfg(feature = "write-conflict")]
+                self.conflicts.lock().record(
+                    page_id,
+                    offset..offset + copy_len,
+                    dirty.contains_key(&page_id),
+                    self.clock.now(),
+                );
+                //标记在页写回前一直有效, 一次数据写入不会取消之前的元数据标记
+                if self.cache_mode == CacheMode::Ordered {
+                    let mut metadata = self.metadata.lock();
+                    if tag == WriteTag::Metadata {
+                        metadata.insert(page_id);
+                    } else if !dirty.contains_key(&page_id) {
+                        metadata.remove(&page_id);
+                    }
+                }
+                dirty.entry(page_id).or_insert_with(|| self.clock.now());
+            }
+            self.note_written(page_id..page_id + 1);
+            count += copy_len;
+            offset = (offset + copy_len) % self.page_size;
+            page_id += 1;
+        }
+        Ok(count)
+    }
+
+    //比较并写入: offset处的当前内容与expected相同时写入new并返回true, 否则不写入并返回false.
+    //读取, 比较和写入期间一直持有缓存锁, 其它读写无法插入其间, 可用于实现磁盘上的简单互斥锁.
+    //写入之后与write相同, WriteThrough模式下返回前写回, 并按脏页上限回写
+    pub fn compare_and_write(
+        &self,
+        offset: usize,
+        expected: &[u8],
+        new: &[u8],
+    ) -> AlienResult<bool> {
+        if expected.len() != new.len() || offset + expected.len() > self.size() {
+            return Err(LinuxErrno::EINVAL);
+        }
+        self.check_faulted()?;
+        self.check_writable()?;
+        let _op = self.begin_op();
+        //持有缓存锁后无法再清空日志, 因此先确保日志放得下这次写入
+        let mut journal = self.journal.as_ref().map(|journal| journal.lock());
+        if let Some(journal) = journal.as_mut() {
+            if new.len() > journal.max_payload() {
+                return Err(LinuxErrno::EINVAL);
+            }
+            if !journal.fits(new.len()) {
+                self.checkpoint(journal)?;
+            }
+        }
+        let mut cache_lock = self.cache.lock();
+        let mut current = vec![0u8; expected.len()];
+        self.read_cached(&mut cache_lock, &mut current, offset)?;
+        if current != expected {
+            return Ok(false);
+        }
+        if let Some(journal) = journal.as_mut() {
+            journal.append(offset, new.len(), Some(new))?;
+        }
+        let count = self.write_cached(&mut cache_lock, new, offset, WriteTag::Data)?;
+        drop(cache_lock);
+        drop(journal);
+        self.finish_write(offset, count)?;
+        Ok(true)
+    }
+
+    //带类型的写入, Ordered模式下tag为Metadata的页在所有数据页写回并刷新之后才写回.
+    //标记持续到页被写回, 其它模式下与write相同
+    pub fn write_tagged(&self, buf: &[u8], offset: usize, tag: WriteTag) -> AlienResult<usize> {
+        self.check_faulted()?;
+        self.check_writable()?;
+        let len = min(buf.len(), self.size().saturating_sub(offset));
+        self.write_with_tag(&buf[..len], offset, tag)
+    }
+
+    //write和write_tagged的公共部分, buf不超过设备末尾
+    fn write_with_tag(&self, buf: &[u8], offset: usize, tag: WriteTag) -> AlienResult<usize> {
+        let _op = self.begin_op();
+        let count = self.journaled(offset, buf.len(), Some(buf), |pos, n| {
+            let mut cache_lock = self.cache.lock();
+            self.write_cached(&mut cache_lock, &buf[pos..pos + n], offset + pos, tag)
+        })?;
+        self.finish_write(offset, count)?;
+        Ok(count)
+    }
+
+    //数据进入缓存之后的公共部分: WriteThrough模式下写回[offset, offset+count), 再按脏页上限回写.
+    //调用者不能持有缓存锁
+    fn finish_write(&self, offset: usize, count: usize) -> AlienResult<()> {
+        if self.cache_mode == CacheMode::WriteThrough && count > 0 {
+            self.write_back_range(offset, count)?;
+        }
+        self.throttle_dirty()
+    }
+
+    //借出offset所在的缓存页, 返回从offset到页末尾(不超过设备末尾)的数据, 不进行拷贝.
+    //页不在缓存中时先读入, 返回的CacheGuard存在期间该页不会被换出
+    pub fn read_borrow(&self, offset: usize) -> AlienResult<CacheGuard<'_>> {
+        if offset >= self.size() {
+            return Err(LinuxErrno::EINVAL);
+        }
+        self.check_faulted()?;
+        let page_id = offset / self.page_size;
+        let mut cache_lock = self.cache.lock();
+        if !cache_lock.contains(&page_id) {
+            self.load_page(&mut cache_lock, page_id)?;
+        }
+        let frame = cache_lock.borrow(page_id).unwrap().ptr;
+        let page_offset = offset % self.page_size;
+        Ok(CacheGuard {
+            device: self,
+            page_id,
+            frame,
+            offset: page_offset,
+            len: min(self.page_size - page_offset, self.size() - offset),
+        })
+    }
+
+    //零拷贝读取[offset, offset+len), 返回直接指向缓存页的CacheGuard.
+    //不同缓存页的帧一般不连续, 因此范围必须位于同一个缓存页内, len为0, 跨页或越过设备末尾时
+    //返回EINVAL. 需要更长的范围时可以用with_page_frames增大缓存页, 或按页分别调用
+    pub fn read_zerocopy(&self, offset: usize, len: usize) -> AlienResult<CacheGuard<'_>> {
+        if len == 0 || offset / self.page_size != (offset + len - 1) / self.page_size {
+            return Err(LinuxErrno::EINVAL);
+        }
+        let mut guard = self.read_borrow(offset)?;
+        if len > guard.len {
+            return Err(LinuxErrno::EINVAL);
+        }
+        guard.len = len;
+        Ok(guard)
+    }
+
+    //同read, 但每读一个缓存页之前检查cancel, 已取消时返回EINTR, 已读出的部分保留在buf中.
+    //每次只在一页的读取期间持有锁, 因此挂起的设备上等待的任务可以在页边界退出
+    pub fn read_cancellable(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        cancel: &dyn Cancel,
+    ) -> AlienResult<usize> {
+        let mut count = 0;
+        while count < buf.len() {
+            if cancel.cancelled() {
+                return Err(LinuxErrno::EINTR);
+            }
+            let pos = offset + count;
+            let n = min(self.page_size - pos % self.page_size, buf.len() - count);
+            let read = self.read(&mut buf[count..count + n], pos)?;
+            count += read;
+            if read < n {
+                break;
+            }
+        }
+        Ok(count)
+    }
+
+    //同write, 但每写一个缓存页之前检查cancel, 已取消时返回EINTR, 之前的页已经写入
+    pub fn write_cancellable(
+        &self,
+        buf: &[u8],
+        offset: usize,
+        cancel: &dyn Cancel,
+    ) -> AlienResult<usize> {
+        let mut count = 0;
+        while count < buf.len() {
+            if cancel.cancelled() {
+                return Err(LinuxErrno::EINTR);
+            }
+            let pos = offset + count;
+            let n = min(self.page_size - pos % self.page_size, buf.len() - count);
+            let written = self.write(&buf[count..count + n], pos)?;
+            count += written;
+            if written < n {
+                break;
+            }
+        }
+        Ok(count)
+    }
+
+    //不经过缓存读取一个扇区, 空洞页中的扇区读出全零
+    fn read_sector(
+        &self,
+        device: &mut dyn LowBlockDevice,
+        sector: usize,
+        buf: &mut [u8],
+    ) -> AlienResult<()> {
+        if self.is_hole(sector * self.sector_size / self.page_size) {
+            buf.fill(0);
+            return Ok(());
+        }
+        self.block_io(IoDir::Read, || device.read_block(sector, buf))
+    }
+
+    //不经过缓存写入一个扇区. 写入空洞页前先在设备上清零整页,
+    //避免页的其余部分在标记为已分配后读出旧数据
+    fn write_sector(
+        &self,
+        device: &mut dyn LowBlockDevice,
+        sector: usize,
+        buf: &[u8],
+    ) -> AlienResult<()> {
+        let page_id = sector * self.sector_size / self.page_size;
+        if self.is_hole(page_id) {
+            let sectors = self.page_size / self.sector_size;
+            self.record_io(
+                IoDir::Write,
+                device.write_zeroes(page_id * sectors, sectors),
+            )?;
+            self.sparse.as_ref().unwrap().lock().set(page_id);
+        }
+        self.note_written(page_id..page_id + 1);
+        self.block_io(IoDir::Write, || device.write_block(sector, buf))
+    }
+}
+
+impl DeviceBase for GenericBlockDevice {
+    //中断处理函数, 请求都是同步完成的, 只需应答中断. 设备容量变化时更新缓存的容量
+    fn hand_irq(&self) {
+        let mut device = self.device.lock();
+        device.ack_interrupt();
+        if device.capacity_changed() {
+            if let Ok(sectors) = device.capacity() {
+                self.sectors.store(sectors, Ordering::Release);
+                self.resized.store(true, Ordering::Release);
+            }
+        }
+    }
+}
+
+impl Debug for GenericBlockDevice {
+    //格式化输出
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("QemuBlockDevice").finish()
+    }
+}
+
+impl BlockDevice for GenericBlockDevice {
+    //读取数据
+    fn read(&self, buf: &mut [u8], offset: usize) -> AlienResult<usize> {
+        self.check_faulted()?;
+        let _op = self.begin_op();
+        let mut cache_lock = self.cache.lock(); //缓存锁
+        self.read_cached(&mut cache_lock, buf, offset)
+    }
+
+    //写入数据, 开启写日志时先追加到日志
+    fn write(&self, buf: &[u8], offset: usize) -> AlienResult<usize> {
+        self.check_faulted()?;
+        self.check_writable()?;
+        let len = min(buf.len(), self.size().saturating_sub(offset));
+        self.write_with_tag(&buf[..len], offset, WriteTag::Data)
+    }
+
+    //获取扇区数, 扇区大小与底层设备的块大小相同
+    fn sector_count(&self) -> usize {
+        self.sectors.load(Ordering::Acquire)
+    }
+
+    fn sector_size(&self) -> usize {
+        self.sector_size
+    }
+
+    //获取设备信息
+    fn info(&self) -> BlockDeviceInfo {
+        self.device.lock().info()
+    }
+
+    //交给底层设备执行. FLUSH先写回所有脏页, DISCARD先使范围内的缓存页失效,
+    //其它命令不经过缓存, 修改了设备内容时调用者需要之后调用invalidate_all
+    fn command(&self, req: &[u8], resp: &mut [u8]) -> AlienResult<usize> {
+        self.check_faulted()?;
+        match read_u32_le(req, 0).ok_or(LinuxErrno::EINVAL)? {
+            BLK_CMD_FLUSH => {
+                BlockDevice::flush(self)?;
+                Ok(0)
+            }
+            BLK_CMD_DISCARD => {
+                self.check_writable()?;
+                let sector = read_u64_le(req, 8).ok_or(LinuxErrno::EINVAL)? as usize;
+                let count =
+                    read_u32_le(req, BLK_CMD_HEADER_SIZE).ok_or(LinuxErrno::EINVAL)? as usize;
+                let mut lru = self.cache.lock();
+                let mut device = self.device.lock();
+                self.invalidate(
+                    &mut lru,
+                    device.as_mut(),
+                    sector * SECTOR_SIZE,
+                    count * SECTOR_SIZE,
+                )?;
+                device.command(req, resp)
+            }
+            _ => self.device.lock().command(req, resp),
+        }
+    }
+
+    //健康状态: 处于故障状态时为Failing, 出现过错误或重试时为Degraded, 再与底层设备报告的合并
+    fn health(&self) -> BlockHealth {
+        let read_errors = self.read_errors.load(Ordering::Relaxed);
+        let write_errors = self.write_errors.load(Ordering::Relaxed);
+        let retries = self.retries.load(Ordering::Relaxed);
+        let status = if self.is_faulted() {
+            HealthStatus::Failing
+        } else if read_errors + write_errors + retries > 0 {
+            HealthStatus::Degraded
+        } else {
+            HealthStatus::Healthy
+        };
+        let health = BlockHealth {
+            read_errors,
+            write_errors,
+            retries,
+            status,
+        };
+        health.merge(self.device.lock().health())
+    }
+
+    fn optimal_io_size(&self) -> usize {
+        self.device.lock().optimal_io_size()
+    }
+
+    //刷新, 写回所有脏页后刷新底层设备, 开启写日志时之后清空日志
+    fn flush(&self) -> AlienResult<()> {
+        self.check_faulted()?;
+        match &self.journal {
+            Some(journal) => self.checkpoint(&mut journal.lock()),
+            None => {
+                let lru = self.cache.lock();
+                self.flush_locked(&lru)
+            }
+        }
+    }
+
+    //底层设备需要刷新, 或缓存中还有未写回的脏页
+    fn needs_flush(&self) -> bool {
+        let device = self.device.lock();
+        device.needs_flush() || !self.dirty.lock().is_empty()
+    }
+
+    //写入缓存后立即写回涉及的页并刷新设备
+    fn write_barrier(&self, buf: &[u8], offset: usize) -> AlienResult<usize> {
+        let len = self.write(buf, offset)?;
+        if len == 0 {
+            return Ok(0);
+        }
+        self.write_back_range(offset, len)?;
+        self.record_io(IoDir::Write, self.device.lock().flush())?;
+        Ok(len)
+    }
+
+    //访问模式提示
+    fn advise(&self, offset: usize, len: usize, hint: Advice) {
+        if len == 0 {
+            return;
+        }
+        let start_page = offset / self.page_size;
+        let end_page = (offset + len - 1) / self.page_size + 1;
+        match hint {
+            Advice::Normal => self
+                .sequential
+                .lock()
+                .retain(|r| r.end <= start_page || r.start >= end_page),
+            Advice::Sequential => self.sequential.lock().push(start_page..end_page),
+            Advice::WillNeed => {
+                let end_page = min(end_page, self.page_count());
+                let mut cache_lock = self.cache.lock();
+                let capacity = cache_lock.capacity();
+                for id in (start_page..end_page).take(capacity) {
+                    if !cache_lock.contains(&id) && self.load_page(&mut cache_lock, id).is_err() {
+                        break;
+                    }
+                }
+            }
+            Advice::DontNeed => {
+                let mut lru = self.cache.lock();
+                let mut device = self.device.lock();
+                let mut dirty = self.dirty.lock();
+                for id in lru.cached_in(start_page..end_page) {
+                    //固定的页保持常驻
+                    if lru.is_pinned(&id) {
+                        continue;
+                    }
+                    if dirty.contains_key(&id) {
+                        //写回失败的页保留在缓存中
+                        if self
+                            .write_data_first(device.as_mut(), &lru, &mut dirty, &[id])
+                            .is_err()
+                        {
+                            continue;
+                        }
+                        let page = lru.peek(&id).unwrap();
+                        if self.write_back(device.as_mut(), id, page).is_err() {
+                            continue;
+                        }
+                        dirty.remove(&id);
+                    }
+                    lru.pop(&id);
+                }
+            }
+        }
+    }
+
+    //清零, 首尾不完整的页经过缓存, 中间的整页丢弃缓存后直接在设备上清零.
+    //与write相同, 越过设备末尾的部分被截断, offset+len溢出时返回EINVAL
+    fn write_zeroes(&self, offset: usize, len: usize) -> AlienResult<()> {
+        self.check_writable()?;
+        //经过缓存清零[start, end)
+        let zero_cached = |start: usize, end: usize| -> AlienResult<()> {
+            let zeros = [0u8; FRAME_SIZE];
+            let mut pos = start;
+            while pos < end {
+                let copy_len = min(FRAME_SIZE, end - pos);
+                self.write(&zeros[..copy_len], pos)?;
+                pos += copy_len;
+            }
+            Ok(())
+        };
+        let end = offset.checked_add(len).ok_or(LinuxErrno::EINVAL)?;
+        let end = min(end, self.size());
+        if offset >= end {
+            return Ok(());
+        }
+        let first_page = offset.div_ceil(self.page_size); //第一个完整页
+        let last_page = end / self.page_size; //完整页结束(不含)
+        if first_page >= last_page {
+            //范围内没有完整的页
+            return zero_cached(offset, end);
+        }
+        zero_cached(offset, first_page * self.page_size)?;
+        zero_cached(last_page * self.page_size, end)?;
+        self.check_faulted()?;
+        let start = first_page * self.page_size;
+        let len = (last_page - first_page) * self.page_size;
+        let _op = self.begin_op();
+        self.journaled(start, len, None, |_, n| {
+            let mut lru = self.cache.lock();
+            let mut device = self.device.lock();
+            let mut dirty = self.dirty.lock();
+            for id in first_page..last_page {
+                lru.pop(&id);
+                dirty.remove(&id);
+            }
+            self.drop_victims(first_page..last_page);
+            self.record_io(
+                IoDir::Write,
+                device.write_zeroes(start / self.sector_size, n / self.sector_size),
+            )?;
+            Ok(n)
+        })?;
+        self.note_written(first_page..last_page);
+        //清零的页重新视为空洞
+        if let Some(sparse) = &self.sparse {
+            let mut sparse = sparse.lock();
+            for id in first_page..last_page {
+                sparse.clear(id);
+            }
+        }
+        Ok(())
+    }
+}
+
+//底层请求目前都是同步完成的, 直接调用阻塞的read
+impl AsyncBlockDevice for GenericBlockDevice {
+    fn poll_read(
+        &self,
+        buf: &mut [u8],
+        offset: usize,
+        _cx: &mut Context<'_>,
+    ) -> Poll<AlienResult<usize>> {
+        Poll::Ready(self.read(buf, offset))
+    }
+}
+
+//VirtIO块设备特性位
+const VIRTIO_BLK_F_SIZE_MAX: u64 = 1 << 1;
+const VIRTIO_BLK_F_SEG_MAX: u64 = 1 << 2;
+const VIRTIO_BLK_F_BLK_SIZE: u64 = 1 << 6;
+const VIRTIO_BLK_F_FLUSH: u64 = 1 << 9;
+const VIRTIO_BLK_F_TOPOLOGY: u64 = 1 << 10;
+
+//VirtIO块设备配置空间(只列出用到的前半部分)
+#[repr(C)]
+struct VirtIOBlkConfig {
+    capacity_low: u32,
+    capacity_high: u32,
+    size_max: u32,
+    seg_max: u32,
+    cylinders: u16,
+    heads: u8,
+    sectors: u8,
+    blk_size: u32,
+    physical_block_exp: u8,
+    alignment_offset: u8,
+    min_io_size: u16,
+    opt_io_size: u32,
+}
+
+//实现 低级块设备 for VirtIOBlkWrapper
+pub struct VirtIOBlkWrapper {
+    device: VirtIOBlk<HalImpl, MmioTransport>,
+    config: Option<NonNull<VirtIOBlkConfig>>, //配置空间
+    features: u64,                            //设备提供的特性
+    capacity: u64,                            //当前容量, 以512字节为单位
+    resized: bool,                            //容量变化后尚未被capacity_changed取走
+}
+
+impl VirtIOBlkWrapper {
+    //构造函数
+    pub fn new(addr: usize) -> Self {
+        let header = NonNull::new(addr as *mut VirtIOHeader).unwrap();
+        let transport = unsafe { MmioTransport::new(header) }.unwrap();
+        Self::from_mmio(transport)
+    }
+
+    //从MMIO创建
+    pub fn from_mmio(mmio_transport: MmioTransport) -> Self {
+        let mut mmio_transport = mmio_transport;
+        let features = mmio_transport.read_device_features();
+        let config = mmio_transport.config_space::<VirtIOBlkConfig>().ok();
+        let blk = VirtIOBlk::<HalImpl, MmioTransport>::new(mmio_transport)
+            .expect("failed to create blk driver");
+        let capacity = blk.capacity();
+        Self {
+            device: blk,
+            config,
+            features,
+            capacity,
+            resized: false,
+        }
+    }
+
+    //从配置空间重新读取容量, VirtIOBlk::capacity只返回初始化时的值
+    fn config_capacity(&self) -> Option<u64> {
+        let config = self.config?.as_ptr();
+        unsafe {
+            let low = addr_of!((*config).capacity_low).read_volatile() as u64;
+            let high = addr_of!((*config).capacity_high).read_volatile() as u64;
+            Some(high << 32 | low)
+        }
+    }
+
+    //逻辑块大小
+    fn blk_size(&self) -> usize {
+        match self.config {
+            Some(config) if self.features & VIRTIO_BLK_F_BLK_SIZE != 0 => unsafe {
+                addr_of!((*config.as_ptr()).blk_size).read_volatile() as usize
+            },
+            _ => 512,
+        }
+    }
+
+    //物理块大小 = 逻辑块大小 << physical_block_exp
+    fn physical_block_size(&self) -> usize {
+        match self.config {
+            Some(config) if self.features & VIRTIO_BLK_F_TOPOLOGY != 0 => {
+                let exp =
+                    unsafe { addr_of!((*config.as_ptr()).physical_block_exp).read_volatile() };
+                self.blk_size() << exp
+            }
+            _ => self.blk_size(),
+        }
+    }
+}
+
+impl LowBlockDevice for VirtIOBlkWrapper {
+    //读取块, buf必须恰好是一个逻辑块, 请求中的扇区号总是以512字节为单位
+    fn read_block(&mut self, block_id: usize, buf: &mut [u8]) -> AlienResult<()> {
+        let blk_size = self.blk_size();
+        if buf.len() != blk_size {
+            return Err(LinuxErrno::EINVAL);
+        }
+        let sector = block_id * (blk_size / SECTOR_SIZE);
+        for (i, chunk) in buf.chunks_mut(SECTOR_SIZE).enumerate() {
+            self.device
+                .read_block(sector + i, chunk)
+                .map_err(|_| LinuxErrno::EIO)?;
+        }
+        Ok(())
+    }
+
+    //写入块, buf必须恰好是一个逻辑块
+    fn write_block(&mut self, block_id: usize, buf: &[u8]) -> AlienResult<()> {
+        let blk_size = self.blk_size();
+        if buf.len() != blk_size {
+            return Err(LinuxErrno::EINVAL);
+        }
+        let sector = block_id * (blk_size / SECTOR_SIZE);
+        for (i, chunk) in buf.chunks(SECTOR_SIZE).enumerate() {
+            self.device
+                .write_block(sector + i, chunk)
+                .map_err(|_| LinuxErrno::EIO)?;
+        }
+        Ok(())
+    }
+
+    //获取设备信息
+    fn info(&self) -> BlockDeviceInfo {
+        BlockDeviceInfo {
+            logical_sector_size: self.blk_size(),
+            physical_sector_size: self.physical_block_size(),
+            total_sectors: self.capacity().unwrap(),
+            supports_trim: false, //驱动未实现discard请求
+            supports_flush: self.features & VIRTIO_BLK_F_FLUSH != 0,
+        }
+    }
+
+    //获取容量(逻辑块数), 配置空间中的capacity总是以512字节为单位
+    fn capacity(&self) -> AlienResult<usize> {
+        Ok(self.capacity as usize * SECTOR_SIZE / self.blk_size())
+    }
+
+    //逻辑块大小, 由VIRTIO_BLK_F_BLK_SIZE给出
+    fn block_size(&self) -> usize {
+        self.blk_size()
+    }
+
+    //优先使用拓扑信息中的opt_io_size, 否则取一个请求最多能携带的数据量
+    fn optimal_io_size(&self) -> usize {
+        let config = match self.config {
+            Some(config) => config.as_ptr(),
+            None => return self.blk_size(),
+        };
+        if self.features & VIRTIO_BLK_F_TOPOLOGY != 0 {
+            let opt = unsafe { addr_of!((*config).opt_io_size).read_volatile() } as usize;
+            if opt != 0 {
+                return opt * self.blk_size();
+            }
+        }
+        let size_max = VIRTIO_BLK_F_SIZE_MAX | VIRTIO_BLK_F_SEG_MAX;
+        if self.features & size_max == size_max {
+            let size_max = unsafe { addr_of!((*config).size_max).read_volatile() } as usize;
+            let seg_max = unsafe { addr_of!((*config).seg_max).read_volatile() } as usize;
+            if size_max != 0 && seg_max != 0 {
+                return size_max * seg_max;
+            }
+        }
+        self.blk_size()
+    }
+
+    //应答中断, 清除设备的中断状态.
+    //virtio-drivers不区分配置变更中断, 每次中断都重新读取配置空间中的容量来发现在线扩容
+    fn ack_interrupt(&mut self) {
+        self.device.ack_interrupt();
+        if let Some(capacity) = self.config_capacity() {
+            if capacity != self.capacity {
+                self.capacity = capacity;
+                self.resized = true;
+            }
+        }
+    }
+
+    fn capacity_changed(&mut self) -> bool {
+        core::mem::take(&mut self.resized)
+    }
+
+    //virtio-drivers没有提供WRITE_ZEROES请求, write_zeroes使用默认的逐块写零
+
+    //刷新设备的易失性写缓存, virtio-blk没有FUA标志, 屏障写依赖写后刷新
+    fn flush(&mut self) -> AlienResult<()> {
+        if self.features & VIRTIO_BLK_F_FLUSH == 0 {
+            return Ok(());
+        }
+        self.device.flush().map_err(|_| LinuxErrno::EIO)
+    }
+
+    //设备没有易失性写缓存时flush为空操作
+    fn needs_flush(&self) -> bool {
+        self.features & VIRTIO_BLK_F_FLUSH != 0
+    }
+
+    //设备协商了VIRTIO_BLK_F_RO
+    fn read_only(&self) -> bool {
+        self.device.readonly()
+    }
+
+    //支持FLUSH, GET_ID和DISCARD. virtio-drivers没有提供discard请求, DISCARD以写零代替,
+    //范围必须按逻辑块对齐
+    fn command(&mut self, req: &[u8], resp: &mut [u8]) -> AlienResult<usize> {
+        match read_u32_le(req, 0).ok_or(LinuxErrno::EINVAL)? {
+            BLK_CMD_FLUSH => {
+                self.flush()?;
+                Ok(0)
+            }
+            BLK_CMD_GET_ID => {
+                let mut id = [0u8; 20];
+                let len = self
+                    .device
+                    .device_id(&mut id)
+                    .map_err(|_| LinuxErrno::EIO)?;
+                let len = min(len, resp.len());
+                resp[..len].copy_from_slice(&id[..len]);
+                Ok(len)
+            }
+            BLK_CMD_DISCARD => {
+                if self.read_only() {
+                    return Err(LinuxErrno::EROFS);
+                }
+                let sector = read_u64_le(req, 8).ok_or(LinuxErrno::EINVAL)?;
+                let count = read_u32_le(req, BLK_CMD_HEADER_SIZE).ok_or(LinuxErrno::EINVAL)? as u64;
+                let ratio = (self.blk_size() / SECTOR_SIZE) as u64;
+                let end = sector.checked_add(count).ok_or(LinuxErrno::EINVAL)?;
+                if end > self.capacity
+                    || !sector.is_multiple_of(ratio)
+                    || !count.is_multiple_of(ratio)
+                {
+                    return Err(LinuxErrno::EINVAL);
+                }
+                self.write_zeroes((sector / ratio) as usize, (count / ratio) as usize)?;
+                Ok(0)
+            }
+            _ => Err(LinuxErrno::EOPNOTSUPP),
+        }
+    }
+}
+
+pub struct MemoryFat32Img {
+    //内存FAT32镜像
+    data: &'static mut [u8],
+}
+
+impl LowBlockDevice for MemoryFat32Img {
+    //读取块
+    fn read_block(&mut self, block_id: usize, buf: &mut [u8]) -> AlienResult<()> {
+        let start = block_id * 512;
+        let end = start + 512;
+        buf.copy_from_slice(&self.data[start..end]);
+        Ok(())
+    }
+    //写入块
+    fn write_block(&mut self, block_id: usize, buf: &[u8]) -> AlienResult<()> {
+        let start = block_id * 512;
+        let end = start + 512;
+        self.data[start..end].copy_from_slice(buf);
+        Ok(())
+    }
+
+    //获取容量
+    fn capacity(&self) -> AlienResult<usize> {
+        Ok(self.data.len() / 512)
+    }
+
+    //数据就在内存中, 无需刷新
+    fn needs_flush(&self) -> bool {
+        false
+    }
+
+    //直接清零内存
+    fn write_zeroes(&mut self, block_id: usize, count: usize) -> AlienResult<()> {
+        self.fill(block_id, count, 0)
+    }
+}
+
+impl MemoryFat32Img {
+    //构造函数
+    pub fn new(data: &'static mut [u8]) -> Self {
+        Self { data }
+    }
+
+    //将从block_start开始的block_count个块全部填充为value
+    pub fn fill(&mut self, block_start: usize, block_count: usize, value: u8) -> AlienResult<()> {
+        match block_start.checked_add(block_count) {
+            Some(end) if end <= self.data.len() / 512 => {
+                self.data[block_start * 512..end * 512].fill(value);
+                Ok(())
+            }
+            _ => Err(LinuxErrno::EINVAL),
+        }
+    }
+
+    //清零整个镜像
+    pub fn zero_all(&mut self) {
+        self.data.fill(0);
+    }
+}
+
+//内存块设备, 数据保存在堆上, 可以在运行时扩容
+pub struct RamDisk {
+    data: Vec<u8>,
+    block_size: usize, //块大小
+}
+
+impl RamDisk {
+    //构造函数, 创建sectors个全零的512字节扇区
+    pub fn new(sectors: usize) -> Self {
+        Self::with_block_size(sectors, SECTOR_SIZE)
+    }
+
+    //创建blocks个全零块, 块大小为block_size字节, 用于模拟4KiB扇区等设备
+    pub fn with_block_size(blocks: usize, block_size: usize) -> Self {
+        assert!(
+            block_size >= SECTOR_SIZE && block_size.is_multiple_of(SECTOR_SIZE),
+            "block size must be a multiple of 512"
+        );
+        Self {
+            data: vec![0; blocks * block_size],
+            block_size,
+        }
+    }
+
+    //调整为new_sectors个块, 新增的块为全零. 缩小时被截掉的块必须全零,
+    //否则返回EINVAL且不做修改.
+    //GenericBlockDevice在构造时读取容量, 调整后需要重新构造才能看到新的大小
+    pub fn resize(&mut self, new_sectors: usize) -> AlienResult<()> {
+        let new_len = new_sectors * self.block_size;
+        if new_len < self.data.len() && self.data[new_len..].iter().any(|&b| b != 0) {
+            return Err(LinuxErrno::EINVAL);
+        }
+        self.data.resize(new_len, 0);
+        Ok(())
+    }
+
+    //从block_id开始的len字节在data中的范围, len不是块大小的整数倍或越界时返回EINVAL
+    fn range(&self, block_id: usize, len: usize) -> AlienResult<Range<usize>> {
+        let start = block_id * self.block_size;
+        if !len.is_multiple_of(self.block_size) || start + len > self.data.len() {
+            return Err(LinuxErrno::EINVAL);
+        }
+        Ok(start..start + len)
+    }
+}
+
+impl LowBlockDevice for RamDisk {
+    fn read_block(&mut self, block_id: usize, buf: &mut [u8]) -> AlienResult<()> {
+        if buf.len() != self.block_size {
+            return Err(LinuxErrno::EINVAL);
+        }
+        self.read_blocks(block_id, buf)
+    }
+
+    fn write_block(&mut self, block_id: usize, buf: &[u8]) -> AlienResult<()> {
+        if buf.len() != self.block_size {
+            return Err(LinuxErrno::EINVAL);
+        }
+        self.write_blocks(block_id, buf)
+    }
+
+    fn read_blocks(&mut self, block_id: usize, buf: &mut [u8]) -> AlienResult<()> {
+        let range = self.range(block_id, buf.len())?;
+        buf.copy_from_slice(&self.data[range]);
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, block_id: usize, buf: &[u8]) -> AlienResult<()> {
+        let range = self.range(block_id, buf.len())?;
+        self.data[range].copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn capacity(&self) -> AlienResult<usize> {
+        Ok(self.data.len() / self.block_size)
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn needs_flush(&self) -> bool {
+        false
+    }
+
+    fn write_zeroes(&mut self, block_id: usize, count: usize) -> AlienResult<()> {
+        let start = block_id * self.block_size;
+        let end = (block_id + count) * self.block_size;
+        if end > self.data.len() {
+            return Err(LinuxErrno::EINVAL);
+        }
+        self.data[start..end].fill(0);
+        Ok(())
+    }
+}
+
+pub use visionfive2_sd::Vf2SdDriver;
+pub struct VF2SDDriver {
+    driver: Vf2SdDriver,
+}
+
+impl VF2SDDriver {
+    //构造函数
+    pub fn new(vf2sd_driver: Vf2SdDriver) -> Self {
+        Self {
+            driver: vf2sd_driver,
+        }
+    }
+    //初始化
+    pub fn init(&self) {
+        self.driver.init();
+    }
+}
+
+impl LowBlockDevice for VF2SDDriver {
+    //读取块
+    fn read_block(&mut self, block_id: usize, buf: &mut [u8]) -> AlienResult<()> {
+        self.driver.read_block(block_id, buf);
+        Ok(())
+    }
+
+    //写入块
+    fn write_block(&mut self, block_id: usize, buf: &[u8]) -> AlienResult<()> {
+        self.driver.write_block(block_id, buf);
+        Ok(())
+    }
+
+    //驱动无法读取卡的容量
+    fn capacity(&self) -> AlienResult<usize> {
+        Err(LinuxErrno::EOPNOTSUPP)
+    }
+
+    //驱动每次只传输一个块, 不支持多块读写
+    fn optimal_io_size(&self) -> usize {
+        512
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::adapter::BlockAsLow;
+    use super::fault::{FaultInjectBlock, FaultRule};
+    use super::*;
+
+    const PAGE: usize = PAGE_CACHE_SIZE;
+
+    //偏移和长度覆盖页首, 页中间, 页末尾和跨越多页的情况
+    const OFFSETS: [usize; 7] = [0, 1, 511, PAGE - 1, PAGE, PAGE + 100, 3 * PAGE - 7];
+    const LENS: [usize; 7] = [1, 511, PAGE - 1, PAGE, PAGE + 1, 3 * PAGE + 7, 5 * PAGE];
+
+    //块大小为block_size的64KiB内存盘上的缓存, frames为None时使用默认容量
+    fn device_with(block_size: usize, frames: Option<usize>) -> GenericBlockDevice {
+        let ram = RamDisk::with_block_size(64 * 1024 / block_size, block_size);
+        let device = GenericBlockDevice::new(Box::new(ram)).unwrap();
+        match frames {
+            Some(frames) => device.with_cache_frames(frames),
+            None => device,
+        }
+    }
+
+    fn device(frames: Option<usize>) -> GenericBlockDevice {
+        device_with(SECTOR_SIZE, frames)
+    }
+
+    //与位置相关的测试数据, 不同的写入用seed区分
+    fn pattern(offset: usize, len: usize, seed: u8) -> Vec<u8> {
+        (offset..offset + len)
+            .map(|i| (i % 251) as u8 ^ seed)
+            .collect()
+    }
+
+    //写入[offset, offset+len)后, 经过缓存和绕过缓存读到的内容都与参考一致
+    fn check_write_read(dev: GenericBlockDevice, offset: usize, len: usize) {
+        let mut model = vec![0u8; dev.size()];
+        //先写满整个设备, 使未写入的部分也能检查出错位
+        let base = pattern(0, dev.size(), 0x5a);
+        dev.write(&base, 0).unwrap();
+        model.copy_from_slice(&base);
+
+        let data = pattern(offset, len, 0xa5);
+        assert_eq!(dev.write(&data, offset).unwrap(), len);
+        model[offset..offset + len].copy_from_slice(&data);
+
+        let mut buf = vec![0u8; len];
+        assert_eq!(dev.read(&mut buf, offset).unwrap(), len);
+        assert_eq!(buf, data, "offset {} len {}", offset, len);
+        //前后各多读一段, 检查相邻的字节没有被覆盖
+        let start = offset.saturating_sub(PAGE + 3);
+        let end = min(offset + len + PAGE + 3, dev.size());
+        let mut around = vec![0u8; end - start];
+        dev.read(&mut around, start).unwrap();
+        assert_eq!(around, model[start..end], "offset {} len {}", offset, len);
+
+        BlockDevice::flush(&dev).unwrap();
+        let mut on_disk = vec![0u8; dev.size()];
+        dev.uncached_handle().read(&mut on_disk, 0).unwrap();
+        assert_eq!(on_disk, model, "offset {} len {}", offset, len);
+    }
+
+    #[test]
+    fn unaligned_write_read_roundtrip() {
+        for &offset in OFFSETS.iter() {
+            for &len in LENS.iter() {
+                check_write_read(device(None), offset, len);
+            }
+        }
+    }
+
+    //缓存只有两页, 跨越多页的读写在拷贝过程中不断换出之前的页
+    #[test]
+    fn unaligned_write_read_with_evictions() {
+        for &offset in OFFSETS.iter() {
+            for &len in LENS.iter() {
+                check_write_read(device(Some(2)), offset, len);
+            }
+        }
+    }
+
+    //512字节和4KiB扇区的设备, 4KiB扇区时每页恰好一个块
+    #[test]
+    fn sector_sizes_512_and_4096() {
+        for &block_size in [SECTOR_SIZE, 4096].iter() {
+            let dev = device_with(block_size, None);
+            assert_eq!(dev.sector_size(), block_size);
+            assert_eq!(dev.size(), 64 * 1024);
+            assert_eq!(dev.sector_count(), 64 * 1024 / block_size);
+            for &offset in OFFSETS.iter() {
+                for &len in LENS.iter() {
+                    check_write_read(device_with(block_size, Some(2)), offset, len);
+                }
+            }
+        }
+    }
+
+    //读写在设备末尾截断, 返回实际的长度
+    #[test]
+    fn access_crossing_device_end_is_truncated() {
+        let dev = device(Some(2));
+        let size = dev.size();
+        let data = pattern(0, 3 * PAGE, 1);
+        assert_eq!(dev.write(&data, size - PAGE - 10).unwrap(), PAGE + 10);
+        let mut buf = vec![0u8; 3 * PAGE];
+        assert_eq!(dev.read(&mut buf, size - PAGE - 10).unwrap(), PAGE + 10);
+        assert_eq!(buf[..PAGE + 10], data[..PAGE + 10]);
+        assert_eq!(dev.read(&mut buf, size).unwrap(), 0);
+    }
+
+    //跨越设备末尾的清零与write一样截断, 中间的整页和首尾的部分页都只清零到末尾
+    #[test]
+    fn write_zeroes_crossing_device_end_is_truncated() {
+        let dev = device(Some(2));
+        let size = dev.size();
+        let data = pattern(0, size, 2);
+        dev.write(&data, 0).unwrap();
+        let offset = size - 2 * PAGE - 100;
+        dev.write_zeroes(offset, 4 * PAGE).unwrap();
+        let mut model = data.clone();
+        model[offset..].fill(0);
+        let mut buf = vec![0u8; size];
+        dev.read(&mut buf, 0).unwrap();
+        assert_eq!(buf, model);
+        BlockDevice::flush(&dev).unwrap();
+        dev.uncached_handle().read(&mut buf, 0).unwrap();
+        assert_eq!(buf, model);
+
+        dev.write_zeroes(size, PAGE).unwrap();
+        assert_eq!(
+            dev.write_zeroes(offset, usize::MAX),
+            Err(LinuxErrno::EINVAL)
+        );
+    }
+
+    //连续写回失败进入故障状态后, 后台回写不再访问设备
+    #[test]
+    fn background_flush_stops_when_faulted() {
+        let ram = GenericBlockDevice::new(Box::new(RamDisk::new(128))).unwrap();
+        let fault = Arc::new(FaultInjectBlock::new(Arc::new(ram), 1));
+        let dev = GenericBlockDevice::new(Box::new(BlockAsLow::new(fault.clone(), 0))).unwrap();
+        dev.write(&[1; 2 * PAGE], 0).unwrap();
+        fault.add_rule(FaultRule::Random(1000));
+        while !dev.is_faulted() {
+            assert!(dev.background_flush(1).is_err());
+        }
+        fault.clear_rules();
+        assert_eq!(dev.background_flush(2), Err(LinuxErrno::EIO));
+        assert_eq!(dev.flush_older_than(0), Err(LinuxErrno::EIO));
+        assert_eq!(fault.op_count(), 0);
+    }
+
+    //WriteThrough模式下compare_and_write成功返回时数据已经写到设备上
+    #[test]
+    fn compare_and_write_is_write_through() {
+        let dev = device(None).with_cache_mode(CacheMode::WriteThrough);
+        let mut buf = [0u8; 8];
+        assert!(!dev.compare_and_write(100, &[1; 8], &[2; 8]).unwrap());
+        assert!(dev.compare_and_write(100, &[0; 8], &[2; 8]).unwrap());
+        assert!(dev.dirty.lock().is_empty());
+        dev.uncached_handle().read(&mut buf, 100).unwrap();
+        assert_eq!(buf, [2; 8]);
+    }
+
+    //未命中的页合并为连续的区间, 每个区间只获取一次设备锁, 而不是每页一次
+    #[test]
+    fn device_lock_taken_once_per_miss_run() {
+        const LEN: usize = 64 * 1024;
+        let dev = device(None);
+        let before = dev.device.acquisitions();
+        dev.write(&pattern(0, LEN, 4), 0).unwrap();
+        assert_eq!(dev.device.acquisitions() - before, 1);
+
+        //第3页和第9页已在缓存中, 未命中的页分为3段
+        let dev = device(None);
+        let mut buf = vec![0u8; 10];
+        dev.read(&mut buf, 3 * PAGE).unwrap();
+        dev.read(&mut buf, 9 * PAGE).unwrap();
+        let before = dev.device.acquisitions();
+        dev.write(&pattern(0, LEN, 4), 0).unwrap();
+        assert_eq!(dev.device.acquisitions() - before, 3);
+
+        let dev = device(None);
+        let mut buf = vec![0u8; LEN];
+        let before = dev.device.acquisitions();
+        dev.read(&mut buf, 0).unwrap();
+        assert_eq!(dev.device.acquisitions() - before, 1);
+    }
+
+    //多个写者同时各写64KiB未命中的区域, 设备锁的获取次数等于写者数, 与页数无关
+    #[test]
+    fn concurrent_writers_take_device_lock_once_each() {
+        extern crate std;
+        const WRITERS: usize = 4;
+        const LEN: usize = 64 * 1024;
+        let dev = Arc::new(
+            GenericBlockDevice::new(Box::new(RamDisk::new(WRITERS * LEN / SECTOR_SIZE))).unwrap(),
+        );
+        let before = dev.device.acquisitions();
+        let writers: Vec<_> = (0..WRITERS)
+            .map(|i| {
+                let dev = dev.clone();
+                std::thread::spawn(move || {
+                    let data = pattern(i * LEN, LEN, i as u8);
+                    assert_eq!(dev.write(&data, i * LEN).unwrap(), LEN);
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(dev.device.acquisitions() - before, WRITERS);
+        let mut buf = vec![0u8; WRITERS * LEN];
+        dev.read(&mut buf, 0).unwrap();
+        for i in 0..WRITERS {
+            assert_eq!(buf[i * LEN..(i + 1) * LEN], pattern(i * LEN, LEN, i as u8));
+        }
+    }
+
+    //每次请求都能传输整个预读窗口的内存盘
+    struct LargeIo(RamDisk);
+
+    impl LowBlockDevice for LargeIo {
+        fn read_block(&mut self, block_id: usize, buf: &mut [u8]) -> AlienResult<()> {
+            self.0.read_block(block_id, buf)
+        }
+
+        fn write_block(&mut self, block_id: usize, buf: &[u8]) -> AlienResult<()> {
+            self.0.write_block(block_id, buf)
+        }
+
+        fn capacity(&self) -> AlienResult<usize> {
+            self.0.capacity()
+        }
+
+        fn optimal_io_size(&self) -> usize {
+            READAHEAD_PAGES * PAGE
+        }
+    }
+
+    //连续未命中之后遇到命中的页时, 预读不能在拷贝之前换出这一页
+    #[test]
+    fn hit_after_misses_survives_readahead() {
+        let dev = GenericBlockDevice::new(Box::new(LargeIo(RamDisk::new(128))))
+            .unwrap()
+            .with_cache_frames(4);
+        let data = pattern(0, dev.size(), 3);
+        dev.uncached_handle().write(&data, 0).unwrap();
+        let mut buf = vec![0u8; 100];
+        dev.read(&mut buf, 3 * PAGE).unwrap();
+        dev.advise(0, dev.size(), Advice::Sequential);
+        let mut buf = vec![0u8; 2 * PAGE];
+        dev.read(&mut buf, 2 * PAGE + 10).unwrap();
+        assert_eq!(buf, data[2 * PAGE + 10..4 * PAGE + 10]);
+    }
+}
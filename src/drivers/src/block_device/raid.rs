@@ -0,0 +1,193 @@
+use super::SECTOR_SIZE;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use config::FRAME_SIZE;
+use constants::{AlienResult, LinuxErrno};
+use core::cmp::min;
+use device_interface::{BlockDevice, BlockHealth, DeviceBase};
+
+//镜像(RAID1)块设备
+pub struct Raid1 {
+    devices: Vec<Arc<dyn BlockDevice>>, //镜像成员
+    quorum: usize,                      //写入成功所需的最少成员数
+}
+
+impl Raid1 {
+    //构造函数, 默认要求所有成员都写入成功
+    pub fn new(devices: Vec<Arc<dyn BlockDevice>>) -> Self {
+        assert!(!devices.is_empty(), "raid1 needs at least one device");
+        let quorum = devices.len();
+        Self { devices, quorum }
+    }
+
+    //设置写入成功所需的最少成员数
+    pub fn with_quorum(mut self, quorum: usize) -> Self {
+        assert!(quorum > 0 && quorum <= self.devices.len());
+        self.quorum = quorum;
+        self
+    }
+
+    //获取成员
+    pub fn devices(&self) -> &[Arc<dyn BlockDevice>] {
+        &self.devices
+    }
+}
+
+impl DeviceBase for Raid1 {
+    //镜像设备本身没有中断
+    fn hand_irq(&self) {}
+}
+
+impl BlockDevice for Raid1 {
+    //从第一个正常的成员读取, 遇到EIO时尝试下一个
+    fn read(&self, buf: &mut [u8], offset: usize) -> AlienResult<usize> {
+        for device in self.devices.iter() {
+            match device.read(buf, offset) {
+                Err(LinuxErrno::EIO) => continue,
+                res => return res,
+            }
+        }
+        Err(LinuxErrno::EIO)
+    }
+
+    //写入所有成员, 成功数不少于quorum时返回成功
+    fn write(&self, buf: &[u8], offset: usize) -> AlienResult<usize> {
+        let mut success = 0;
+        let mut written = buf.len();
+        let mut error = LinuxErrno::EIO;
+        for device in self.devices.iter() {
+            match device.write(buf, offset) {
+                Ok(len) => {
+                    success += 1;
+                    written = min(written, len);
+                }
+                Err(e) => error = e,
+            }
+        }
+        if success >= self.quorum {
+            Ok(written)
+        } else {
+            Err(error)
+        }
+    }
+
+    //大小为所有成员中最小的
+    fn sector_count(&self) -> usize {
+        self.devices.iter().map(|d| d.size()).min().unwrap_or(0) / SECTOR_SIZE
+    }
+
+    //刷新所有成员
+    fn flush(&self) -> AlienResult<()> {
+        let mut success = 0;
+        let mut error = LinuxErrno::EIO;
+        for device in self.devices.iter() {
+            match device.flush() {
+                Ok(()) => success += 1,
+                Err(e) => error = e,
+            }
+        }
+        if success >= self.quorum {
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+
+    fn needs_flush(&self) -> bool {
+        self.devices.iter().any(|device| device.needs_flush())
+    }
+
+    //所有成员的合并, 任一成员失效都会反映在状态中
+    fn health(&self) -> BlockHealth {
+        self.devices
+            .iter()
+            .fold(BlockHealth::default(), |health, device| {
+                health.merge(device.health())
+            })
+    }
+}
+
+//将src的内容完整复制到dst, 用于替换成员后的重建
+pub fn rebuild(src: &dyn BlockDevice, dst: &dyn BlockDevice) -> AlienResult<()> {
+    let size = min(src.size(), dst.size());
+    let mut buf = vec![0u8; FRAME_SIZE];
+    let mut offset = 0;
+    while offset < size {
+        let len = min(FRAME_SIZE, size - offset);
+        src.read(&mut buf[..len], offset)?;
+        dst.write(&buf[..len], offset)?;
+        offset += len;
+    }
+    dst.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fault::{FaultInjectBlock, FaultRule};
+    use super::super::{GenericBlockDevice, RamDisk};
+    use super::*;
+    use alloc::boxed::Box;
+
+    fn ram(sectors: usize) -> Arc<dyn BlockDevice> {
+        Arc::new(GenericBlockDevice::new(Box::new(RamDisk::new(sectors))).unwrap())
+    }
+
+    //第一个成员的每次操作都返回EIO
+    fn failing_first() -> (Arc<FaultInjectBlock>, Arc<dyn BlockDevice>, Raid1) {
+        let good = ram(64);
+        let bad = Arc::new(FaultInjectBlock::new(ram(64), 1));
+        let raid = Raid1::new(vec![bad.clone() as Arc<dyn BlockDevice>, good.clone()]);
+        (bad, good, raid)
+    }
+
+    #[test]
+    fn read_falls_back_to_next_member_on_eio() {
+        let (bad, _, raid) = failing_first();
+        raid.write(&[7u8; 1000], 300).unwrap();
+        bad.add_rule(FaultRule::Random(1000));
+        let mut buf = [0u8; 1000];
+        assert_eq!(raid.read(&mut buf, 300).unwrap(), 1000);
+        assert!(buf.iter().all(|&b| b == 7));
+    }
+
+    #[test]
+    fn read_fails_when_every_member_fails() {
+        let bad = Arc::new(FaultInjectBlock::new(ram(64), 1));
+        bad.add_rule(FaultRule::Random(1000));
+        let raid = Raid1::new(vec![bad as Arc<dyn BlockDevice>]);
+        let mut buf = [0u8; 10];
+        assert_eq!(raid.read(&mut buf, 0), Err(LinuxErrno::EIO));
+    }
+
+    #[test]
+    fn write_needs_quorum() {
+        let (bad, good, raid) = failing_first();
+        bad.add_rule(FaultRule::Random(1000));
+        assert_eq!(raid.write(&[1u8; 10], 0), Err(LinuxErrno::EIO));
+
+        let raid = raid.with_quorum(1);
+        assert_eq!(raid.write(&[2u8; 10], 0).unwrap(), 10);
+        let mut buf = [0u8; 10];
+        good.read(&mut buf, 0).unwrap();
+        assert_eq!(buf, [2u8; 10]);
+    }
+
+    #[test]
+    fn size_is_smallest_member() {
+        let raid = Raid1::new(vec![ram(64), ram(32)]);
+        assert_eq!(raid.size(), 32 * SECTOR_SIZE);
+    }
+
+    #[test]
+    fn rebuild_copies_replaced_member() {
+        let src = ram(64);
+        let data: Vec<u8> = (0..src.size()).map(|i| i as u8).collect();
+        src.write(&data, 0).unwrap();
+        let dst = ram(64);
+        rebuild(src.as_ref(), dst.as_ref()).unwrap();
+        let mut buf = vec![0u8; dst.size()];
+        dst.read(&mut buf, 0).unwrap();
+        assert_eq!(buf, data);
+    }
+}
@@ -0,0 +1,83 @@
+//页缓存一致性自检: 在内存盘上的GenericBlockDevice中执行随机的读写, 清零和回写,
+//每次读取的结果和最后设备上的内容都与字节数组参考模型比较. 缓存只有几页, 访问的范围
+//在页中间开始和结束并跨越多页, 因此频繁换出脏页. 相同的seed产生相同的操作序列
+use super::fault::next_random;
+use super::{GenericBlockDevice, RamDisk, PAGE_CACHE_SIZE};
+use alloc::boxed::Box;
+use alloc::vec;
+use constants::{AlienResult, LinuxErrno};
+use core::cmp::min;
+use device_interface::BlockDevice;
+use log::warn;
+
+const DISK_SECTORS: usize = 256; //128KiB的内存盘
+const CACHE_FRAMES: usize = 4;
+const MAX_LEN: usize = 3 * PAGE_CACHE_SIZE; //单次访问的最大长度
+
+//执行ops次随机操作, 读到的内容或最后设备上的内容与参考模型不一致时返回EIO,
+//并记录第一次不一致的操作, 之后可以用同一个seed重现
+pub fn check_cache_consistency(seed: u64, ops: usize) -> AlienResult<()> {
+    let dev = GenericBlockDevice::new(Box::new(RamDisk::new(DISK_SECTORS)))?
+        .with_cache_frames(CACHE_FRAMES);
+    let size = dev.size();
+    let mut model = vec![0u8; size];
+    let mut buf = vec![0u8; MAX_LEN];
+    let mut rng = seed.max(1); //xorshift的状态不能为0
+    for op in 0..ops {
+        let offset = next_random(&mut rng) as usize % size;
+        let len = min(next_random(&mut rng) as usize % MAX_LEN + 1, size - offset);
+        match next_random(&mut rng) % 16 {
+            0 => BlockDevice::flush(&dev)?,
+            1 => dev.sync_range(offset, len)?,
+            2 => {
+                dev.write_zeroes(offset, len)?;
+                model[offset..offset + len].fill(0);
+            }
+            3..=8 => {
+                let seed = next_random(&mut rng) as u8;
+                for (i, b) in buf[..len].iter_mut().enumerate() {
+                    *b = seed.wrapping_add(i as u8);
+                }
+                if dev.write(&buf[..len], offset)? != len {
+                    warn!("cache self-check: short write at op {}", op);
+                    return Err(LinuxErrno::EIO);
+                }
+                model[offset..offset + len].copy_from_slice(&buf[..len]);
+            }
+            _ => {
+                let count = dev.read(&mut buf[..len], offset)?;
+                if count != len || buf[..len] != model[offset..offset + len] {
+                    warn!(
+                        "cache self-check: read mismatch at op {}, offset {} len {}",
+                        op, offset, len
+                    );
+                    return Err(LinuxErrno::EIO);
+                }
+            }
+        }
+    }
+    BlockDevice::flush(&dev)?;
+    let mut disk = vec![0u8; size];
+    dev.uncached_handle().read(&mut disk, 0)?;
+    if disk != model {
+        let first = disk.iter().zip(model.iter()).position(|(a, b)| a != b);
+        warn!(
+            "cache self-check: device differs from model at byte {:?}",
+            first
+        );
+        return Err(LinuxErrno::EIO);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_ops_match_reference_model() {
+        for seed in 1..=8 {
+            check_cache_consistency(seed, 4000).unwrap();
+        }
+    }
+}
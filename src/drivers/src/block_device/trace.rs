@@ -0,0 +1,130 @@
+use alloc::sync::Arc;
+use constants::AlienResult;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use device_interface::{Advice, BlockDevice, BlockDeviceInfo, BlockHealth, DeviceBase};
+use log::{debug, log_enabled, Level};
+
+//透明的跟踪块设备, 转发前以debug级别记录每次读写和刷新的偏移、长度和结果,
+//用于重现文件系统损坏时查看完整的I/O顺序
+pub struct TracingBlock {
+    inner: Arc<dyn BlockDevice>,
+    min_len: AtomicUsize, //忽略长度小于min_len的读写
+}
+
+impl TracingBlock {
+    //构造函数, 默认记录所有请求
+    pub fn new(inner: Arc<dyn BlockDevice>) -> Self {
+        Self {
+            inner,
+            min_len: AtomicUsize::new(0),
+        }
+    }
+
+    //只记录长度不小于min_len的读写
+    pub fn set_filter(&self, min_len: usize) {
+        self.min_len.store(min_len, Ordering::Relaxed);
+    }
+
+    //日志级别关闭时只需一次比较
+    fn enabled(&self, len: usize) -> bool {
+        log_enabled!(Level::Debug) && len >= self.min_len.load(Ordering::Relaxed)
+    }
+}
+
+impl DeviceBase for TracingBlock {
+    fn hand_irq(&self) {
+        self.inner.hand_irq()
+    }
+}
+
+impl BlockDevice for TracingBlock {
+    fn read(&self, buf: &mut [u8], offset: usize) -> AlienResult<usize> {
+        let len = buf.len();
+        let res = self.inner.read(buf, offset);
+        if self.enabled(len) {
+            debug!("[blk] read offset={:#x} len={} -> {:?}", offset, len, res);
+        }
+        res
+    }
+
+    fn write(&self, buf: &[u8], offset: usize) -> AlienResult<usize> {
+        let res = self.inner.write(buf, offset);
+        if self.enabled(buf.len()) {
+            debug!(
+                "[blk] write offset={:#x} len={} -> {:?}",
+                offset,
+                buf.len(),
+                res
+            );
+        }
+        res
+    }
+
+    fn sector_count(&self) -> usize {
+        self.inner.sector_count()
+    }
+
+    fn sector_size(&self) -> usize {
+        self.inner.sector_size()
+    }
+
+    fn optimal_io_size(&self) -> usize {
+        self.inner.optimal_io_size()
+    }
+
+    fn flush(&self) -> AlienResult<()> {
+        let res = self.inner.flush();
+        if log_enabled!(Level::Debug) {
+            debug!("[blk] flush -> {:?}", res);
+        }
+        res
+    }
+
+    fn needs_flush(&self) -> bool {
+        self.inner.needs_flush()
+    }
+
+    fn health(&self) -> BlockHealth {
+        self.inner.health()
+    }
+
+    fn command(&self, req: &[u8], resp: &mut [u8]) -> AlienResult<usize> {
+        let res = self.inner.command(req, resp);
+        if log_enabled!(Level::Debug) {
+            debug!("[blk] command len={} -> {:?}", req.len(), res);
+        }
+        res
+    }
+
+    fn write_barrier(&self, buf: &[u8], offset: usize) -> AlienResult<usize> {
+        let res = self.inner.write_barrier(buf, offset);
+        if self.enabled(buf.len()) {
+            debug!(
+                "[blk] write_barrier offset={:#x} len={} -> {:?}",
+                offset,
+                buf.len(),
+                res
+            );
+        }
+        res
+    }
+
+    fn write_zeroes(&self, offset: usize, len: usize) -> AlienResult<()> {
+        let res = self.inner.write_zeroes(offset, len);
+        if self.enabled(len) {
+            debug!(
+                "[blk] write_zeroes offset={:#x} len={} -> {:?}",
+                offset, len, res
+            );
+        }
+        res
+    }
+
+    fn advise(&self, offset: usize, len: usize, hint: Advice) {
+        self.inner.advise(offset, len, hint)
+    }
+
+    fn info(&self) -> BlockDeviceInfo {
+        self.inner.info()
+    }
+}
@@ -0,0 +1,90 @@
+use super::GenericBlockDevice;
+use alloc::vec;
+use constants::AlienResult;
+use core::cmp::min;
+use device_interface::BlockDevice;
+
+//绕过页缓存直接读写底层设备的句柄.
+//每次访问前使重叠的缓存页失效(脏页先写回), 并在访问期间持有缓存锁,
+//因此与经过缓存的读写保持一致
+pub struct UncachedBlock<'a> {
+    device: &'a GenericBlockDevice,
+}
+
+impl<'a> UncachedBlock<'a> {
+    pub(super) fn new(device: &'a GenericBlockDevice) -> Self {
+        Self { device }
+    }
+
+    //读取数据, 返回读取的字节数
+    pub fn read(&self, buf: &mut [u8], offset: usize) -> AlienResult<usize> {
+        self.device.check_faulted()?;
+        let len = min(buf.len(), self.device.size().saturating_sub(offset));
+        let mut lru = self.device.cache.lock();
+        let mut device = self.device.device.lock();
+        self.device
+            .invalidate(&mut lru, device.as_mut(), offset, len)?;
+        let sector_size = self.device.sector_size;
+        let mut sector_buf = vec![0u8; sector_size];
+        let mut count = 0;
+        while count < len {
+            let pos = offset + count;
+            let sector = pos / sector_size;
+            let sector_offset = pos % sector_size;
+            let copy_len = min(sector_size - sector_offset, len - count);
+            if copy_len == sector_size {
+                let target = &mut buf[count..count + sector_size];
+                self.device.read_sector(device.as_mut(), sector, target)?;
+            } else {
+                self.device
+                    .read_sector(device.as_mut(), sector, &mut sector_buf)?;
+                buf[count..count + copy_len]
+                    .copy_from_slice(&sector_buf[sector_offset..sector_offset + copy_len]);
+            }
+            count += copy_len;
+        }
+        Ok(count)
+    }
+
+    //写入数据, 不完整的扇区读-改-写, 返回写入的字节数. 开启写日志时先追加到日志
+    pub fn write(&self, buf: &[u8], offset: usize) -> AlienResult<usize> {
+        self.device.check_faulted()?;
+        self.device.check_writable()?;
+        let len = min(buf.len(), self.device.size().saturating_sub(offset));
+        self.device
+            .journaled(offset, len, Some(&buf[..len]), |pos, n| {
+                self.write_sectors(&buf[pos..pos + n], offset + pos)
+            })
+    }
+
+    //写入已截断到设备末尾的数据
+    fn write_sectors(&self, buf: &[u8], offset: usize) -> AlienResult<usize> {
+        let len = buf.len();
+        let mut lru = self.device.cache.lock();
+        let mut device = self.device.device.lock();
+        self.device
+            .invalidate(&mut lru, device.as_mut(), offset, len)?;
+        let sector_size = self.device.sector_size;
+        let mut sector_buf = vec![0u8; sector_size];
+        let mut count = 0;
+        while count < len {
+            let pos = offset + count;
+            let sector = pos / sector_size;
+            let sector_offset = pos % sector_size;
+            let copy_len = min(sector_size - sector_offset, len - count);
+            if copy_len == sector_size {
+                let source = &buf[count..count + sector_size];
+                self.device.write_sector(device.as_mut(), sector, source)?;
+            } else {
+                self.device
+                    .read_sector(device.as_mut(), sector, &mut sector_buf)?;
+                sector_buf[sector_offset..sector_offset + copy_len]
+                    .copy_from_slice(&buf[count..count + copy_len]);
+                self.device
+                    .write_sector(device.as_mut(), sector, &sector_buf)?;
+            }
+            count += copy_len;
+        }
+        Ok(count)
+    }
+}
@@ -6,5 +6,6 @@ pub mod gpu;
 pub mod hal;
 pub mod input;
 pub mod net;
+pub mod raw;
 pub mod rtc;
 pub mod uart;
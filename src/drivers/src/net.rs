@@ -1,9 +1,14 @@
+use core::cmp::min;
 use core::ptr::NonNull;
 
 use crate::hal::HalImpl;
+use constants::{AlienResult, LinuxErrno};
+use device_interface::{DeviceBase, NetDevice};
+use ksync::Mutex;
 pub use loopback::LoopbackDev;
 use netcore::{KernelNetFunc, NetInstant};
 use timer::TimeSpec;
+use virtio_drivers::device::net::VirtIONet;
 use virtio_drivers::transport::mmio::{MmioTransport, VirtIOHeader};
 use virtio_net::VirtIONetDeviceWrapper;
 
@@ -23,6 +28,68 @@ impl VirtIONetDriver {
     }
 }
 
+// Raw frame access to a virtio-net device, for users that don't go through netcore.
+pub struct VirtIONetWrapper {
+    net: Mutex<VirtIONet<HalImpl, MmioTransport, NET_QUEUE_SIZE>>,
+}
+
+unsafe impl Send for VirtIONetWrapper {}
+
+unsafe impl Sync for VirtIONetWrapper {}
+
+impl VirtIONetWrapper {
+    pub fn from_mmio(mmio: MmioTransport) -> Self {
+        let net = VirtIONet::new(mmio, NET_BUFFER_LEN).expect("failed to create net driver");
+        Self {
+            net: Mutex::new(net),
+        }
+    }
+}
+
+impl DeviceBase for VirtIONetWrapper {
+    fn hand_irq(&self) {
+        self.net.lock().ack_interrupt();
+    }
+}
+
+impl NetDevice for VirtIONetWrapper {
+    fn send(&self, buf: &[u8]) -> AlienResult<usize> {
+        let mut net = self.net.lock();
+        if !net.can_send() {
+            return Err(LinuxErrno::EAGAIN);
+        }
+        let mut tx_buf = net.new_tx_buffer(buf.len());
+        tx_buf.packet_mut().copy_from_slice(buf);
+        net.send(tx_buf).map_err(|_| LinuxErrno::EIO)?;
+        Ok(buf.len())
+    }
+
+    fn receive(&self, buf: &mut [u8]) -> AlienResult<usize> {
+        let mut net = self.net.lock();
+        if !net.can_recv() {
+            return Err(LinuxErrno::EAGAIN);
+        }
+        let rx_buf = net.receive().map_err(|_| LinuxErrno::EIO)?;
+        let packet = rx_buf.packet();
+        let len = min(packet.len(), buf.len());
+        buf[..len].copy_from_slice(&packet[..len]);
+        net.recycle_rx_buffer(rx_buf).map_err(|_| LinuxErrno::EIO)?;
+        Ok(len)
+    }
+
+    fn mac_address(&self) -> [u8; 6] {
+        self.net.lock().mac_address()
+    }
+
+    fn can_send(&self) -> bool {
+        self.net.lock().can_send()
+    }
+
+    fn can_receive(&self) -> bool {
+        self.net.lock().can_recv()
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct NetNeedFunc;
 #[derive(Debug, Default)]
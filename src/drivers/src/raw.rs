@@ -0,0 +1,24 @@
+//从原始扇区数据中按小端序读取字段, 越界时返回None
+
+//读取buf[off..off+N]
+fn read_array<const N: usize>(buf: &[u8], off: usize) -> Option<[u8; N]> {
+    let end = off.checked_add(N)?;
+    buf.get(off..end)?.try_into().ok()
+}
+
+pub fn read_u16_le(buf: &[u8], off: usize) -> Option<u16> {
+    read_array(buf, off).map(u16::from_le_bytes)
+}
+
+pub fn read_u32_le(buf: &[u8], off: usize) -> Option<u32> {
+    read_array(buf, off).map(u32::from_le_bytes)
+}
+
+pub fn read_u64_le(buf: &[u8], off: usize) -> Option<u64> {
+    read_array(buf, off).map(u64::from_le_bytes)
+}
+
+//读取GUID, 保持磁盘上的字节顺序(前三段为小端序)
+pub fn read_guid(buf: &[u8], off: usize) -> Option<[u8; 16]> {
+    read_array(buf, off)
+}
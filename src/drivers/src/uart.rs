@@ -3,6 +3,8 @@ pub use self::uart8250::Uart8250;
 use alloc::boxed::Box;
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use device_interface::{DeviceBase, UartDevice};
 use ksync::Mutex;
 use shim::KTask;
@@ -11,13 +13,64 @@ pub trait LowUartDriver: Send + Sync {
     fn _init(&mut self);
     fn _put(&mut self, c: u8);
     fn _read(&mut self) -> Option<u8>;
+    // program the closest rate the hardware supports and return it,
+    // None when the rate cannot be changed
+    fn _set_baud(&mut self, _baud: u32) -> Option<u32> {
+        None
+    }
+    // nothing is left in the transmit FIFO or shift register
+    fn _tx_empty(&mut self) -> bool {
+        true
+    }
+    // bytes the transmitter takes without waiting, 0 when it is busy
+    fn _tx_space(&mut self) -> usize {
+        1
+    }
+}
+
+// 8250-compatible register layout
+const UART_DLL: usize = 0;
+const UART_DLM: usize = 1;
+const UART_IIR: usize = 2;
+const UART_LCR: usize = 3;
+const UART_LSR: usize = 5;
+const IIR_FIFO_ENABLED: u8 = 0xc0;
+const LCR_DLAB: u8 = 0x80;
+const LSR_THRE: u8 = 0x20;
+const LSR_TEMT: u8 = 0x40;
+// the smallest 16550-compatible transmit FIFO
+const TX_FIFO_DEPTH: usize = 16;
+
+// divisor latch value for `baud` with the given input clock and the rate it gives
+fn divisor_for(clock: u32, baud: u32) -> (u16, u32) {
+    let (clock, baud) = (clock as u64, baud as u64);
+    let div = ((clock + 8 * baud) / (16 * baud)).clamp(1, u16::MAX as u64);
+    (div as u16, (clock / (16 * div)) as u32)
+}
+
+// room in the transmitter from LSR and IIR: THRE means the holding register is empty,
+// or the whole FIFO when FIFOs are enabled. Reading IIR clears a pending THRE
+// interrupt, which is never enabled here
+fn tx_space(lsr: u8, iir: u8) -> usize {
+    if lsr & LSR_THRE == 0 {
+        0
+    } else if iir & IIR_FIFO_ENABLED == IIR_FIFO_ENABLED {
+        TX_FIFO_DEPTH
+    } else {
+        1
+    }
 }
 
 mod uart8250 {
-    use crate::uart::LowUartDriver;
+    use crate::uart::{
+        divisor_for, tx_space, LowUartDriver, LCR_DLAB, LSR_TEMT, UART_DLL, UART_DLM, UART_IIR,
+        UART_LCR, UART_LSR,
+    };
 
     pub struct Uart8250 {
         uart_raw: uart8250::MmioUart8250<'static, u32>,
+        base_addr: usize,
+        clock: Option<u32>,
     }
 
     unsafe impl Send for Uart8250 {}
@@ -27,7 +80,22 @@ mod uart8250 {
     impl Uart8250 {
         pub fn new(base_addr: usize) -> Self {
             let uart_raw = unsafe { uart8250::MmioUart8250::<u32>::new(base_addr) };
-            Uart8250 { uart_raw }
+            Uart8250 {
+                uart_raw,
+                base_addr,
+                clock: None,
+            }
+        }
+
+        // input clock in Hz, needed to change the baud rate
+        pub fn with_clock(mut self, clock: u32) -> Self {
+            self.clock = Some(clock);
+            self
+        }
+
+        // registers are 32 bits wide and 4 bytes apart
+        fn reg(&self, index: usize) -> *mut u32 {
+            (self.base_addr + index * 4) as *mut u32
         }
     }
 
@@ -47,14 +115,50 @@ mod uart8250 {
         fn _read(&mut self) -> Option<u8> {
             self.uart_raw.read_byte()
         }
+
+        fn _set_baud(&mut self, baud: u32) -> Option<u32> {
+            let clock = self.clock?;
+            if baud == 0 {
+                return None;
+            }
+            let (div, actual) = divisor_for(clock, baud);
+            unsafe {
+                let lcr = self.reg(UART_LCR).read_volatile();
+                self.reg(UART_LCR).write_volatile(lcr | LCR_DLAB as u32);
+                self.reg(UART_DLL).write_volatile((div & 0xff) as u32);
+                self.reg(UART_DLM).write_volatile((div >> 8) as u32);
+                self.reg(UART_LCR).write_volatile(lcr);
+            }
+            Some(actual)
+        }
+
+        fn _tx_empty(&mut self) -> bool {
+            let lsr = unsafe { self.reg(UART_LSR).read_volatile() };
+            lsr & LSR_TEMT as u32 != 0
+        }
+
+        fn _tx_space(&mut self) -> usize {
+            let (lsr, iir) = unsafe {
+                (
+                    self.reg(UART_LSR).read_volatile(),
+                    self.reg(UART_IIR).read_volatile(),
+                )
+            };
+            tx_space(lsr as u8, iir as u8)
+        }
     }
 }
 
 mod uart16550 {
-    use crate::uart::LowUartDriver;
+    use crate::uart::{
+        divisor_for, tx_space, LowUartDriver, LCR_DLAB, LSR_TEMT, UART_DLL, UART_DLM, UART_IIR,
+        UART_LCR, UART_LSR,
+    };
 
     pub struct Uart16550 {
         uart_raw: &'static mut uart16550::Uart16550<u8>,
+        base_addr: usize,
+        clock: Option<u32>,
     }
 
     unsafe impl Send for Uart16550 {}
@@ -64,7 +168,22 @@ mod uart16550 {
     impl Uart16550 {
         pub fn new(base_addr: usize) -> Self {
             let uart_raw = unsafe { &mut *(base_addr as *mut uart16550::Uart16550<u8>) };
-            Uart16550 { uart_raw }
+            Uart16550 {
+                uart_raw,
+                base_addr,
+                clock: None,
+            }
+        }
+
+        // input clock in Hz, needed to change the baud rate
+        pub fn with_clock(mut self, clock: u32) -> Self {
+            self.clock = Some(clock);
+            self
+        }
+
+        // registers are one byte wide
+        fn reg(&self, index: usize) -> *mut u8 {
+            (self.base_addr + index) as *mut u8
         }
     }
 
@@ -89,6 +208,37 @@ mod uart16550 {
                 Some(buf[0])
             }
         }
+
+        fn _set_baud(&mut self, baud: u32) -> Option<u32> {
+            let clock = self.clock?;
+            if baud == 0 {
+                return None;
+            }
+            let (div, actual) = divisor_for(clock, baud);
+            unsafe {
+                let lcr = self.reg(UART_LCR).read_volatile();
+                self.reg(UART_LCR).write_volatile(lcr | LCR_DLAB);
+                self.reg(UART_DLL).write_volatile((div & 0xff) as u8);
+                self.reg(UART_DLM).write_volatile((div >> 8) as u8);
+                self.reg(UART_LCR).write_volatile(lcr);
+            }
+            Some(actual)
+        }
+
+        fn _tx_empty(&mut self) -> bool {
+            let lsr = unsafe { self.reg(UART_LSR).read_volatile() };
+            lsr & LSR_TEMT != 0
+        }
+
+        fn _tx_space(&mut self) -> usize {
+            let (lsr, iir) = unsafe {
+                (
+                    self.reg(UART_LSR).read_volatile(),
+                    self.reg(UART_IIR).read_volatile(),
+                )
+            };
+            tx_space(lsr, iir)
+        }
     }
 }
 
@@ -149,7 +299,19 @@ impl UartDevice for Uart {
     }
 
     fn have_space_to_put(&self) -> bool {
-        true
+        self.space_to_put() > 0
+    }
+
+    fn space_to_put(&self) -> usize {
+        self.inner.lock().0._tx_space()
+    }
+
+    fn tx_empty(&self) -> bool {
+        self.inner.lock().0._tx_empty()
+    }
+
+    fn set_baud(&self, baud: u32) -> Option<u32> {
+        self.inner.lock().0._set_baud(baud)
     }
 }
 
@@ -170,3 +332,191 @@ impl DeviceBase for Uart {
         }
     }
 }
+
+// In-memory uart for exercising the line discipline without hardware.
+// Bytes pushed with push_input are returned by get, bytes written are collected
+// and can be taken with take_output.
+pub struct LoopbackUart {
+    inner: Mutex<LoopbackInner>,
+}
+
+struct LoopbackInner {
+    input: VecDeque<u8>,
+    output: VecDeque<u8>,
+}
+
+impl LoopbackUart {
+    pub fn new() -> Self {
+        LoopbackUart {
+            inner: Mutex::new(LoopbackInner {
+                input: VecDeque::new(),
+                output: VecDeque::new(),
+            }),
+        }
+    }
+
+    pub fn push_input(&self, bytes: &[u8]) {
+        self.inner.lock().input.extend(bytes);
+    }
+
+    pub fn take_output(&self) -> Vec<u8> {
+        self.inner.lock().output.drain(..).collect()
+    }
+}
+
+impl Default for LoopbackUart {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UartDevice for LoopbackUart {
+    fn put(&self, c: u8) {
+        self.inner.lock().output.push_back(c);
+    }
+
+    fn get(&self) -> Option<u8> {
+        self.inner.lock().input.pop_front()
+    }
+
+    fn put_bytes(&self, bytes: &[u8]) {
+        self.inner.lock().output.extend(bytes);
+    }
+
+    fn have_data_to_get(&self) -> bool {
+        !self.inner.lock().input.is_empty()
+    }
+
+    fn have_space_to_put(&self) -> bool {
+        true
+    }
+
+    // output is unbounded
+    fn space_to_put(&self) -> usize {
+        usize::MAX
+    }
+
+    // there is no line, any rate works
+    fn set_baud(&self, baud: u32) -> Option<u32> {
+        Some(baud)
+    }
+}
+
+impl DeviceBase for LoopbackUart {
+    fn hand_irq(&self) {}
+}
+
+// One logical console over several uarts, e.g. a debug and a data port.
+// Input is taken from whichever member has data, round-robin so a busy member
+// cannot starve the others. Output goes to every member unless one is selected.
+pub struct MultiUart {
+    members: Vec<Arc<dyn UartDevice>>,
+    // member to try first on the next get
+    next: AtomicUsize,
+    // index of the only member written to, ALL_MEMBERS to write to every member
+    output: AtomicUsize,
+}
+
+const ALL_MEMBERS: usize = usize::MAX;
+
+impl MultiUart {
+    pub fn new(members: Vec<Arc<dyn UartDevice>>) -> Self {
+        assert!(!members.is_empty(), "MultiUart needs at least one member");
+        MultiUart {
+            members,
+            next: AtomicUsize::new(0),
+            output: AtomicUsize::new(ALL_MEMBERS),
+        }
+    }
+
+    // write only to members[idx], None writes to every member again
+    pub fn select_output(&self, idx: Option<usize>) {
+        let idx = match idx {
+            Some(idx) => {
+                assert!(idx < self.members.len(), "no such uart member");
+                idx
+            }
+            None => ALL_MEMBERS,
+        };
+        self.output.store(idx, Ordering::Relaxed);
+    }
+
+    // members that output is written to
+    fn outputs(&self) -> &[Arc<dyn UartDevice>] {
+        match self.output.load(Ordering::Relaxed) {
+            ALL_MEMBERS => &self.members,
+            idx => &self.members[idx..idx + 1],
+        }
+    }
+}
+
+impl UartDevice for MultiUart {
+    fn put(&self, c: u8) {
+        for uart in self.outputs() {
+            uart.put(c);
+        }
+    }
+
+    fn get(&self) -> Option<u8> {
+        let count = self.members.len();
+        let start = self.next.load(Ordering::Relaxed);
+        for i in 0..count {
+            let idx = (start + i) % count;
+            let uart = &self.members[idx];
+            if uart.have_data_to_get() {
+                if let Some(c) = uart.get() {
+                    self.next.store((idx + 1) % count, Ordering::Relaxed);
+                    return Some(c);
+                }
+            }
+        }
+        None
+    }
+
+    fn put_bytes(&self, bytes: &[u8]) {
+        for uart in self.outputs() {
+            uart.put_bytes(bytes);
+        }
+    }
+
+    fn have_data_to_get(&self) -> bool {
+        self.members.iter().any(|uart| uart.have_data_to_get())
+    }
+
+    // a write goes to every output, so all of them need room
+    fn have_space_to_put(&self) -> bool {
+        self.outputs().iter().all(|uart| uart.have_space_to_put())
+    }
+
+    fn space_to_put(&self) -> usize {
+        self.outputs()
+            .iter()
+            .map(|uart| uart.space_to_put())
+            .min()
+            .unwrap_or(0)
+    }
+
+    fn tx_empty(&self) -> bool {
+        self.outputs().iter().all(|uart| uart.tx_empty())
+    }
+
+    // applied to every member, returns what the first one chose
+    fn set_baud(&self, baud: u32) -> Option<u32> {
+        let mut res = None;
+        for (i, uart) in self.members.iter().enumerate() {
+            let actual = uart.set_baud(baud);
+            if i == 0 {
+                res = actual;
+            }
+        }
+        res
+    }
+}
+
+impl DeviceBase for MultiUart {
+    fn hand_irq(&self) {
+        for uart in self.members.iter() {
+            uart.hand_irq();
+        }
+    }
+}
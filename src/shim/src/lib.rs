@@ -21,6 +21,10 @@ pub trait KTaskShim: Send + Sync {
     fn suspend(&self);
     fn transfer_ptr_raw(&self, ptr: usize) -> usize;
     fn transfer_buf_raw(&self, src: usize, size: usize) -> Vec<&mut [u8]>;
+    /// Send a signal to every task in the process group. Kernels without
+    /// process groups have to say so explicitly with an empty body, since the
+    /// tty relies on it to deliver SIGWINCH.
+    fn kill_pgrp(&self, pgid: u32, signal: u32);
 }
 
 impl dyn KTaskShim {
@@ -102,6 +106,14 @@ pub fn suspend() {
         .suspend();
 }
 #[cfg(feature = "lib")]
+/// Send a signal to a process group.
+pub fn kill_pgrp(pgid: u32, signal: u32) {
+    KTASK_SHIM
+        .get()
+        .expect("ktask_shim not initialized")
+        .kill_pgrp(pgid, signal);
+}
+#[cfg(feature = "lib")]
 pub fn copy_data_to_task<T: 'static + Copy>(src: *const T, dst: *mut T) {
     KTASK_SHIM
         .get()
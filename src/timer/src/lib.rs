@@ -5,6 +5,8 @@ use platform::config::CLOCK_FREQ;
 use vfscore::utils::VfsTimeSpec;
 /// 每秒包含的毫秒数
 const MSEC_PER_SEC: usize = 1000;
+/// 每秒包含的微秒数
+const USEC_PER_SEC: usize = 1_000_000;
 /// 程序运行时间
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
@@ -127,3 +129,8 @@ pub fn read_timer() -> usize {
 pub fn get_time_ms() -> isize {
     (read_timer() / (CLOCK_FREQ / MSEC_PER_SEC)) as isize
 }
+
+/// 获取当前时间，以 us 为单位
+pub fn get_time_us() -> usize {
+    read_timer() / (CLOCK_FREQ / USEC_PER_SEC)
+}